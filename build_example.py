@@ -1,4 +1,5 @@
 import os
+import shutil
 import sys
 
 available_examples = [
@@ -29,7 +30,13 @@ if example not in available_examples:
     print(f"{example} is not a known example")
     print_examples()
 
-pwsh(f"cargo build --package {example}-example")
-pwsh("New-Item -ItemType Directory plugins")
-pwsh(f"rm plugins/{example}_example.dll")
-pwsh(f"Move-Item -Path target-win/debug/{example}_example.dll -Destination plugins/{example}_example.dll")
+if sys.platform == "win32":
+    pwsh(f"cargo build --package {example}-example")
+    pwsh("New-Item -ItemType Directory plugins")
+    pwsh(f"rm plugins/{example}_example.dll")
+    pwsh(f"Move-Item -Path target-win/debug/{example}_example.dll -Destination plugins/{example}_example.dll")
+else:
+    library = f"lib{example}_example.dylib" if sys.platform == "darwin" else f"lib{example}_example.so"
+    os.system(f"cargo build --package {example}-example")
+    os.makedirs("plugins", exist_ok=True)
+    shutil.copyfile(f"target/debug/{library}", f"plugins/{library}")
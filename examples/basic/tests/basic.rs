@@ -0,0 +1,36 @@
+use xtea_test::{library_path, Harness};
+
+fn harness() -> Harness {
+    Harness::load(library_path("basic-example")).unwrap()
+}
+
+#[test]
+fn shows_its_window() {
+    let mut harness = harness();
+    assert_eq!(harness.name(), "Example (basic) Plugin");
+
+    harness.run_frames(2).assert_window("basic-example");
+    harness.last_frame().unwrap().assert_text("basic-example\nThis is a window");
+    harness.assert_no_warnings();
+}
+
+#[test]
+fn the_view_menu_hides_its_window() {
+    let mut harness = harness();
+    harness.set_view_menu(true);
+    harness.run_frames(2).assert_text_contains("Basic Example Window");
+
+    harness.activate(&["View", "Basic Example Window"]);
+    harness.run_frames(2).assert_no_window("basic-example");
+    harness.activate(&["View", "Basic Example Window"]);
+    harness.run_frames(2).assert_window("basic-example");
+}
+
+#[test]
+fn looks_like_its_golden_image() {
+    let mut harness = harness();
+    harness.set_display_size([240.0, 120.0]);
+    harness.run_frames(2);
+
+    harness.assert_screenshot(concat!(env!("CARGO_MANIFEST_DIR"), "/tests/golden/window.png"));
+}
@@ -1,72 +1,47 @@
-use std::{sync::{Mutex, MutexGuard}, ptr, collections::HashMap};
+use std::{collections::HashMap, error::Error};
 
-use imgui::{Ui, sys::{ImGuiMemFreeFunc, ImGuiMemAllocFunc}};
-use xtealib::ImguiLogger;
-use once_cell::sync::Lazy;
-static mut STATE: Lazy<Mutex<State>> = Lazy::new(|| Mutex::new(State::new()));
+use imgui::Ui;
+use serde::{Deserialize, Serialize};
+use xtealib::XteaPlugin;
 
-fn mut_state() -> &'static mut State {
-    unsafe{STATE.get_mut().unwrap()}
-}
-
-fn state() -> MutexGuard<'static, State> {
-    unsafe{STATE.lock().unwrap()}
-}
-
-struct State {
+// The map is kept when the plugin is reloaded
+#[derive(Serialize, Deserialize)]
+struct NonConstStateExample {
     show_window: bool,
     map: HashMap<String, String>
 }
 
-impl State {
-    fn new() -> State {
+impl XteaPlugin for NonConstStateExample {
+    fn new() -> Result<Self, Box<dyn Error>> {
         let mut map = HashMap::new();
         map.insert("Key1".into(), "Value1".into());
         map.insert("Key2".into(), "Value2".into());
         map.insert("Key3".into(), "Value3".into());
         map.insert("Key4".into(), "Value4".into());
 
-        State {
+        Ok(NonConstStateExample {
             show_window: true,
             map
-        }
+        })
     }
-}
-
-#[no_mangle]
-pub fn init_logger(logger: &'static ImguiLogger) {
-    logger.init().unwrap()
-}
 
-#[no_mangle]
-pub fn init_imgui(ctx: *mut imgui::sys::ImGuiContext, malloc: ImGuiMemAllocFunc, free: ImGuiMemFreeFunc) {
-    unsafe {imgui::sys::igSetCurrentContext(ctx)}
-    unsafe {imgui::sys::igSetAllocatorFunctions(malloc, free, ptr::null_mut())}
-}
-
-#[no_mangle]
-pub fn init_plugin() {
-
-}
+    fn name() -> &'static str {
+        "Example (nonconststate) Plugin"
+    }
 
-#[no_mangle]
-pub fn build_ui(ui: &Ui) {
-    let state = state();
-    if state.show_window {
-        ui.window("nonconststate-example").focus_on_appearing(false).build(|| {
-            for (key, val) in state.map.iter() {
-                ui.label_text(key, val)
-            }
-        });
+    fn build_ui(&mut self, ui: &Ui) {
+        if self.show_window {
+            ui.window("nonconststate-example").focus_on_appearing(false).build(|| {
+                for (key, val) in self.map.iter() {
+                    ui.label_text(key, val)
+                }
+            });
+        }
     }
-}
 
-#[no_mangle]
-pub fn get_name() -> String {
-    String::from("Example (nonconststate) Plugin")
+    fn view_submenu(&mut self, ui: &Ui) {
+        ui.checkbox("Non Const State Example Window", &mut self.show_window);
+    }
 }
 
-#[no_mangle]
-pub fn view_submenu(ui: &Ui) {
-    ui.checkbox("Non Const State Example Window", &mut mut_state().show_window);
-}
+xtealib::xtea_plugin!(NonConstStateExample, serde_state);
@@ -0,0 +1,24 @@
+use xtea_test::{library_path, Harness};
+
+#[test]
+fn shows_every_key_and_value() {
+    let mut harness = Harness::load(library_path("nonconststate-example")).unwrap();
+
+    let frame = harness.run_frames(2);
+    frame.assert_window("nonconststate-example");
+    // The window is narrow, the keys are cut off after their first letters
+    for i in 1..=4 {
+        frame.assert_text_contains(&format!("Value{i} Ke"))
+    }
+    harness.assert_no_warnings();
+}
+
+#[test]
+fn the_view_menu_hides_its_window() {
+    let mut harness = Harness::load(library_path("nonconststate-example")).unwrap();
+    harness.set_view_menu(true);
+    harness.run_frame();
+
+    harness.activate(&["View", "Non Const State Example Window"]);
+    harness.run_frames(2).assert_no_window("nonconststate-example");
+}
@@ -1,35 +1,30 @@
-use std::ptr;
+use std::error::Error;
 
-use imgui::{Ui, sys::{ImGuiMemAllocFunc, ImGuiMemFreeFunc}};
-use xtealib::ImguiLogger;
+use imgui::Ui;
+use xtealib::XteaPlugin;
 
-#[no_mangle]
-pub fn init_logger(logger: &'static ImguiLogger) {
-    logger.init().unwrap()
-}
+struct MyPlugin {
 
-#[no_mangle]
-pub fn init_imgui(ctx: *mut imgui::sys::ImGuiContext, malloc: ImGuiMemAllocFunc, free: ImGuiMemFreeFunc) {
-    unsafe {imgui::sys::igSetCurrentContext(ctx)}
-    unsafe {imgui::sys::igSetAllocatorFunctions(malloc, free, ptr::null_mut())}
 }
 
-#[no_mangle]
-pub fn init_plugin() {
+impl XteaPlugin for MyPlugin {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(MyPlugin {
 
-}
+        })
+    }
 
-#[no_mangle]
-pub fn build_ui(ui: &Ui) {
+    fn name() -> &'static str {
+        "My Plugin Name"
+    }
 
-}
+    fn build_ui(&mut self, _ui: &Ui) {
 
-#[no_mangle]
-pub fn get_name() -> String {
-    String::from("My Plugin Name")
-}
+    }
 
-#[no_mangle]
-pub fn view_submenu(ui: &Ui) {
+    fn view_submenu(&mut self, _ui: &Ui) {
 
+    }
 }
+
+xtealib::xtea_plugin!(MyPlugin);
@@ -0,0 +1,12 @@
+use xtea_test::{library_path, Harness};
+
+#[test]
+fn loads_and_draws_nothing() {
+    let mut harness = Harness::load(library_path("template-example")).unwrap();
+    assert_eq!(harness.name(), "My Plugin Name");
+
+    let frame = harness.run_frames(2);
+    assert!(frame.window_names().is_empty(), "{:?}", frame.window_names());
+    assert_eq!(frame.draw_data.vertex_count(), 0);
+    harness.assert_no_warnings();
+}
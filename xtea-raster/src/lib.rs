@@ -0,0 +1,349 @@
+//! Draws imgui's draw data on the CPU, for screenshots on machines without a GPU.
+//!
+//! Shared by xtea's `--screenshot` and the plugin test harness, so both draw frames the same way.
+
+use std::collections::HashMap;
+
+use image::{Rgba, RgbaImage};
+use imgui::{Context, DrawCmd, DrawVert, TextureId};
+
+/// What the frame asked the renderer to draw, copied out of imgui.
+#[derive(Debug, Clone, Default)]
+pub struct DrawData {
+    pub display_pos: [f32; 2],
+    pub display_size: [f32; 2],
+    pub framebuffer_scale: [f32; 2],
+    pub lists: Vec<DrawList>,
+}
+
+#[derive(Debug, Clone)]
+pub struct DrawList {
+    pub vertices: Vec<DrawVert>,
+    pub indices: Vec<u16>,
+    pub commands: Vec<DrawCommand>,
+}
+
+/// Draws `count` indices starting at `idx_offset`. Render callbacks aren't kept.
+#[derive(Debug, Clone, Copy)]
+pub struct DrawCommand {
+    pub count: usize,
+    /// left, top, right, bottom
+    pub clip_rect: [f32; 4],
+    pub texture_id: TextureId,
+    pub vtx_offset: usize,
+    pub idx_offset: usize,
+}
+
+impl DrawData {
+    /// Copies imgui's draw data, which only lives until the next frame.
+    pub fn capture(draw_data: &imgui::DrawData) -> DrawData {
+        // imgui-rs makes a slice of the list pointer even when it's null
+        let lists = (draw_data.draw_lists_count() > 0).then(|| draw_data.draw_lists()).into_iter().flatten()
+            .map(|list| DrawList {
+                vertices: list.vtx_buffer().to_vec(),
+                indices: list.idx_buffer().to_vec(),
+                commands: list.commands()
+                    .filter_map(|command| match command {
+                        DrawCmd::Elements { count, cmd_params } => Some(DrawCommand {
+                            count,
+                            clip_rect: cmd_params.clip_rect,
+                            texture_id: cmd_params.texture_id,
+                            vtx_offset: cmd_params.vtx_offset,
+                            idx_offset: cmd_params.idx_offset,
+                        }),
+                        DrawCmd::ResetRenderState | DrawCmd::RawCallback { .. } => None,
+                    })
+                    .collect(),
+            })
+            .collect();
+
+        DrawData {
+            display_pos: draw_data.display_pos,
+            display_size: draw_data.display_size,
+            framebuffer_scale: draw_data.framebuffer_scale,
+            lists,
+        }
+    }
+
+    pub fn vertex_count(&self) -> usize {
+        self.lists.iter().map(|list| list.vertices.len()).sum()
+    }
+
+    pub fn triangle_count(&self) -> usize {
+        self.lists.iter().map(|list| list.indices.len() / 3).sum()
+    }
+}
+
+/// The ID the rasterizer gives to the font atlas.
+pub const FONT_TEXTURE: TextureId = TextureId::new(1);
+
+/// Turns draw data into an image, the same way on every machine.
+///
+/// Triangles are filled with the top-left rule, textures are sampled from their nearest texel,
+/// and colors are blended with their alpha like imgui's own renderers do.
+pub struct Rasterizer {
+    textures: HashMap<TextureId, RgbaImage>,
+    clear_color: [u8; 4],
+}
+
+impl Rasterizer {
+    /// Builds the font atlas of `context`, so its fonts must already be added.
+    pub fn new(context: &mut Context) -> Rasterizer {
+        let fonts = context.fonts();
+        let atlas = fonts.build_rgba32_texture();
+        let font_texture = RgbaImage::from_raw(atlas.width, atlas.height, atlas.data.to_vec())
+            .expect("the atlas has 4 bytes per pixel");
+        fonts.tex_id = FONT_TEXTURE;
+
+        Rasterizer {
+            textures: HashMap::from([(FONT_TEXTURE, font_texture)]),
+            clear_color: [0, 0, 0, 255],
+        }
+    }
+
+    /// Adds a texture for `ui.image` and the like. Draw commands with unknown textures only use their vertex colors.
+    pub fn insert_texture(&mut self, id: TextureId, texture: RgbaImage) {
+        self.textures.insert(id, texture);
+    }
+
+    /// What's behind everything, opaque black by default.
+    pub fn set_clear_color(&mut self, clear_color: [u8; 4]) {
+        self.clear_color = clear_color
+    }
+
+    /// Draws a frame, in an image of its display size times its framebuffer scale.
+    pub fn render(&self, draw_data: &DrawData) -> RgbaImage {
+        let scale = draw_data.framebuffer_scale;
+        let width = (draw_data.display_size[0] * scale[0]).round().max(1.0) as u32;
+        let height = (draw_data.display_size[1] * scale[1]).round().max(1.0) as u32;
+        let mut image = RgbaImage::from_pixel(width, height, Rgba(self.clear_color));
+
+        let to_pixels = |pos: [f32; 2]| [
+            (pos[0] - draw_data.display_pos[0]) * scale[0],
+            (pos[1] - draw_data.display_pos[1]) * scale[1],
+        ];
+        for list in &draw_data.lists {
+            for command in &list.commands {
+                let [left, top] = to_pixels([command.clip_rect[0], command.clip_rect[1]]);
+                let [right, bottom] = to_pixels([command.clip_rect[2], command.clip_rect[3]]);
+                let clip = [left.max(0.0), top.max(0.0), right.min(width as f32), bottom.min(height as f32)];
+                if clip[0] >= clip[2] || clip[1] >= clip[3] {
+                    continue
+                }
+
+                let texture = self.textures.get(&command.texture_id).filter(|texture| !texture.is_empty());
+                for triangle in triangles(list, command) {
+                    let vertices = triangle.map(|index| {
+                        let vertex = &list.vertices[command.vtx_offset + index as usize];
+                        Vertex {
+                            pos: to_pixels(vertex.pos),
+                            uv: vertex.uv,
+                            color: vertex.col.map(|channel| channel as f32 / 255.0),
+                        }
+                    });
+                    fill_triangle(&mut image, vertices, clip, texture)
+                }
+            }
+        }
+
+        image
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Vertex {
+    pos: [f32; 2],
+    uv: [f32; 2],
+    color: [f32; 4],
+}
+
+fn triangles<'a>(list: &'a DrawList, command: &DrawCommand) -> impl Iterator<Item = [u16; 3]> + 'a {
+    let indices = list.indices.get(command.idx_offset..command.idx_offset + command.count).unwrap_or_default();
+    indices.chunks_exact(3).map(|triangle| [triangle[0], triangle[1], triangle[2]])
+}
+
+/// Twice the signed area of `a`, `b`, `p`: positive when `p` is on one side of `a`→`b`, negative on the other.
+fn edge(a: [f32; 2], b: [f32; 2], p: [f32; 2]) -> f32 {
+    (b[0] - a[0]) * (p[1] - a[1]) - (b[1] - a[1]) * (p[0] - a[0])
+}
+
+/// Whether a pixel exactly on the edge `from`→`to` belongs to the triangle, which it does on its top and left edges.
+/// Triangles are turned clockwise on screen, so top edges go right and left edges go up.
+/// Two triangles sharing an edge go through it in opposite directions, so exactly one of them gets its pixels.
+fn owns_edge(from: [f32; 2], to: [f32; 2]) -> bool {
+    let (dx, dy) = (to[0] - from[0], to[1] - from[1]);
+    dy < 0.0 || (dy == 0.0 && dx > 0.0)
+}
+
+/// Blends the pixels whose center is inside both the triangle and the clip rectangle.
+fn fill_triangle(image: &mut RgbaImage, [a, mut b, mut c]: [Vertex; 3], clip: [f32; 4], texture: Option<&RgbaImage>) {
+    let mut area = edge(a.pos, b.pos, c.pos);
+    if area == 0.0 {
+        return
+    }
+    // imgui winds its triangles both ways, make them clockwise on screen (y goes down)
+    if area < 0.0 {
+        std::mem::swap(&mut b, &mut c);
+        area = -area;
+    }
+
+    let min = |axis: usize| a.pos[axis].min(b.pos[axis]).min(c.pos[axis]);
+    let max = |axis: usize| a.pos[axis].max(b.pos[axis]).max(c.pos[axis]);
+    // Pixel centers are at +0.5
+    let first = |start: f32| (start - 0.5).ceil().max(0.0) as u32;
+    let end = |end: f32| (end - 0.5).ceil().max(0.0) as u32;
+    let (x_range, y_range) = (
+        first(min(0).max(clip[0]))..end(max(0).min(clip[2])),
+        first(min(1).max(clip[1]))..end(max(1).min(clip[3])),
+    );
+
+    let edges = [(b.pos, c.pos), (c.pos, a.pos), (a.pos, b.pos)];
+    let owned = edges.map(|(from, to)| owns_edge(from, to));
+    for y in y_range {
+        for x in x_range.clone() {
+            let center = [x as f32 + 0.5, y as f32 + 0.5];
+            let weights = edges.map(|(from, to)| edge(from, to, center));
+            let inside = weights.iter().zip(owned).all(|(&weight, owned)| weight > 0.0 || (weight == 0.0 && owned));
+            if !inside {
+                continue
+            }
+
+            let [wa, wb, wc] = weights.map(|weight| weight / area);
+            let interpolate = |values: [f32; 3]| values[0] * wa + values[1] * wb + values[2] * wc;
+            let uv = [0, 1].map(|axis| interpolate([a.uv[axis], b.uv[axis], c.uv[axis]]));
+            let texel = texture.map_or([1.0; 4], |texture| sample(texture, uv));
+            let source: [f32; 4] = std::array::from_fn(|channel| {
+                interpolate([a.color[channel], b.color[channel], c.color[channel]]) * texel[channel]
+            });
+            blend(image.get_pixel_mut(x, y), source)
+        }
+    }
+}
+
+/// The nearest texel, between 0 and 1.
+fn sample(texture: &RgbaImage, uv: [f32; 2]) -> [f32; 4] {
+    let x = ((uv[0] * texture.width() as f32) as u32).min(texture.width() - 1);
+    let y = ((uv[1] * texture.height() as f32) as u32).min(texture.height() - 1);
+
+    texture.get_pixel(x, y).0.map(|channel| channel as f32 / 255.0)
+}
+
+/// Straight alpha over, like imgui's renderers.
+fn blend(pixel: &mut Rgba<u8>, source: [f32; 4]) {
+    let alpha = source[3];
+    let destination = pixel.0.map(|channel| channel as f32 / 255.0);
+    let blended: [f32; 4] = std::array::from_fn(|channel| match channel {
+        3 => alpha + destination[3] * (1.0 - alpha),
+        _ => source[channel] * alpha + destination[channel] * (1.0 - alpha),
+    });
+
+    pixel.0 = blended.map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXTURE: TextureId = TextureId::new(2);
+
+    fn rasterizer() -> Rasterizer {
+        Rasterizer { textures: HashMap::new(), clear_color: [0, 0, 0, 255] }
+    }
+
+    fn draw_data(size: [f32; 2], lists: Vec<DrawList>) -> DrawData {
+        DrawData { display_pos: [0.0, 0.0], display_size: size, framebuffer_scale: [1.0, 1.0], lists }
+    }
+
+    /// A rectangle from `min` to `max`, in two triangles sharing its diagonal, like imgui draws them.
+    fn rect(min: [f32; 2], max: [f32; 2], color: [u8; 4], texture_id: TextureId) -> DrawList {
+        let corners = [[min[0], min[1]], [max[0], min[1]], [max[0], max[1]], [min[0], max[1]]];
+        let uvs = [[0.0, 0.0], [1.0, 0.0], [1.0, 1.0], [0.0, 1.0]];
+        DrawList {
+            vertices: corners.into_iter().zip(uvs).map(|(pos, uv)| DrawVert { pos, uv, col: color }).collect(),
+            indices: vec![0, 1, 2, 0, 2, 3],
+            commands: vec![DrawCommand {
+                count: 6,
+                clip_rect: [0.0, 0.0, f32::MAX, f32::MAX],
+                texture_id,
+                vtx_offset: 0,
+                idx_offset: 0,
+            }],
+        }
+    }
+
+    fn pixels(image: &RgbaImage) -> Vec<[u8; 4]> {
+        image.pixels().map(|pixel| pixel.0).collect()
+    }
+
+    #[test]
+    fn pixels_on_a_shared_edge_are_drawn_once() {
+        // Half transparent, a pixel drawn twice would be lighter
+        let color = [255, 255, 255, 128];
+        let lists = vec![rect([0.0, 0.0], [2.0, 4.0], color, FONT_TEXTURE), rect([2.0, 0.0], [4.0, 4.0], color, FONT_TEXTURE)];
+        let image = rasterizer().render(&draw_data([4.0, 4.0], lists));
+
+        assert!(pixels(&image).iter().all(|&pixel| pixel == [128, 128, 128, 255]), "{:?}", pixels(&image));
+    }
+
+    #[test]
+    fn pixel_centers_on_the_top_and_left_edges_are_inside() {
+        let image = rasterizer().render(&draw_data([4.0, 4.0], vec![rect([0.5, 0.5], [2.5, 2.5], [255; 4], FONT_TEXTURE)]));
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let inside = x < 2 && y < 2;
+            assert_eq!(pixel.0 == [255; 4], inside, "pixel ({x}, {y}) is {:?}", pixel.0)
+        }
+    }
+
+    #[test]
+    fn textures_are_sampled_from_the_nearest_texel() {
+        let texels = [[255, 0, 0, 255], [0, 255, 0, 255], [0, 0, 255, 255], [255, 255, 0, 255]];
+        let mut rasterizer = rasterizer();
+        rasterizer.insert_texture(TEXTURE, RgbaImage::from_fn(2, 2, |x, y| Rgba(texels[(y * 2 + x) as usize])));
+        let image = rasterizer.render(&draw_data([4.0, 4.0], vec![rect([0.0, 0.0], [4.0, 4.0], [255; 4], TEXTURE)]));
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            assert_eq!(pixel.0, texels[(y / 2 * 2 + x / 2) as usize], "pixel ({x}, {y})")
+        }
+    }
+
+    #[test]
+    fn vertex_colors_tint_the_texture() {
+        let mut rasterizer = rasterizer();
+        rasterizer.insert_texture(TEXTURE, RgbaImage::from_pixel(1, 1, Rgba([255, 255, 255, 255])));
+        let image = rasterizer.render(&draw_data([1.0, 1.0], vec![rect([0.0, 0.0], [1.0, 1.0], [0, 255, 0, 255], TEXTURE)]));
+
+        assert_eq!(pixels(&image), [[0, 255, 0, 255]])
+    }
+
+    #[test]
+    fn colors_are_blended_with_their_alpha() {
+        let mut rasterizer = rasterizer();
+        rasterizer.set_clear_color([0, 0, 255, 255]);
+        let image = rasterizer.render(&draw_data([1.0, 1.0], vec![rect([0.0, 0.0], [1.0, 1.0], [255, 0, 0, 64], FONT_TEXTURE)]));
+
+        assert_eq!(pixels(&image), [[64, 0, 191, 255]])
+    }
+
+    #[test]
+    fn commands_are_clipped() {
+        let mut list = rect([0.0, 0.0], [4.0, 4.0], [255; 4], FONT_TEXTURE);
+        list.commands[0].clip_rect = [1.0, 1.0, 3.0, 2.0];
+        let image = rasterizer().render(&draw_data([4.0, 4.0], vec![list]));
+
+        for (x, y, pixel) in image.enumerate_pixels() {
+            let inside = (1..3).contains(&x) && y == 1;
+            assert_eq!(pixel.0 == [255; 4], inside, "pixel ({x}, {y}) is {:?}", pixel.0)
+        }
+    }
+
+    #[test]
+    fn the_image_has_the_framebuffer_size() {
+        let mut data = draw_data([10.0, 5.0], Vec::new());
+        data.framebuffer_scale = [2.0, 2.0];
+        let image = rasterizer().render(&data);
+
+        assert_eq!(image.dimensions(), (20, 10));
+        assert!(pixels(&image).iter().all(|&pixel| pixel == [0, 0, 0, 255]))
+    }
+}
@@ -0,0 +1,66 @@
+//! Line diffs for assertion failures.
+
+/// Lists the lines of `expected` and `actual`, prefixed with `-` when only in `expected`,
+/// `+` when only in `actual`, and two spaces when in both.
+pub fn lines(expected: &str, actual: &str) -> String {
+    let expected: Vec<_> = expected.lines().collect();
+    let actual: Vec<_> = actual.lines().collect();
+
+    // Longest common subsequence, from the end, so the diff can be read from the start.
+    // Where a line changed, the removed line comes before the added one
+    let mut common = vec![vec![0usize; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut diff = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            diff.push_str(&format!("  {}\n", expected[i]));
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || common[i][j + 1] > common[i + 1][j]) {
+            diff.push_str(&format!("+ {}\n", actual[j]));
+            j += 1;
+        } else {
+            diff.push_str(&format!("- {}\n", expected[i]));
+            i += 1;
+        }
+    }
+
+    diff
+}
+
+#[cfg(test)]
+mod tests {
+    use super::lines;
+
+    #[test]
+    fn same_text_has_no_changes() {
+        assert_eq!(lines("a\nb", "a\nb"), "  a\n  b\n")
+    }
+
+    #[test]
+    fn changed_lines_are_removed_then_added() {
+        assert_eq!(lines("a\nb\nc", "a\nB\nc"), "  a\n- b\n+ B\n  c\n")
+    }
+
+    #[test]
+    fn insertions_and_removals_keep_the_common_lines() {
+        assert_eq!(lines("a\nb\nc\nd", "x\na\nc\nd\ny"), "+ x\n  a\n- b\n  c\n  d\n+ y\n")
+    }
+
+    #[test]
+    fn empty_sides() {
+        assert_eq!(lines("", "a"), "+ a\n");
+        assert_eq!(lines("a", ""), "- a\n");
+        assert_eq!(lines("", ""), "")
+    }
+}
@@ -0,0 +1,202 @@
+use std::{collections::HashMap, ffi::CStr};
+
+use imgui::{DrawVert, FontGlyph};
+use xtea_raster::DrawData;
+use xtealib::LogRecord;
+
+use crate::diff;
+
+/// Everything a frame produced.
+#[derive(Debug, Clone)]
+pub struct Frame {
+    /// Counted from 0 since the harness was created.
+    pub index: usize,
+    /// The text drawn in every window, in the order of [`windows`](Frame::windows). See [`WindowInfo::text`].
+    pub text: String,
+    pub windows: Vec<WindowInfo>,
+    pub draw_data: DrawData,
+    /// The log records emitted during this frame.
+    pub logs: Vec<LogRecord>,
+}
+
+/// A window that was submitted during the frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowInfo {
+    /// The full name, with its `##` and `###` suffixes.
+    pub name: String,
+    pub pos: [f32; 2],
+    pub size: [f32; 2],
+    pub collapsed: bool,
+    /// Appearing windows are hidden on their first frame, while imgui measures them.
+    pub hidden: bool,
+    pub focused: bool,
+    /// Child windows are named after their parent, like `Parent/Child_1A2B3C4D`.
+    pub child: bool,
+    /// The text drawn in the window, line by line, read back from its draw list. Its title bar comes first.
+    /// Items on the same line are separated by a space, and what isn't text (like a checkmark) is left out.
+    pub text: String,
+}
+
+impl Frame {
+    pub fn window(&self, name: &str) -> Option<&WindowInfo> {
+        self.windows.iter().find(|window| window.name == name)
+    }
+
+    /// The names of the windows that aren't child windows.
+    pub fn window_names(&self) -> Vec<&str> {
+        self.windows.iter().filter(|window| !window.child).map(|window| window.name.as_str()).collect()
+    }
+
+    /// Panics, listing the frame's windows, if there's no window called `name`.
+    #[track_caller]
+    pub fn assert_window(&self, name: &str) -> &WindowInfo {
+        match self.window(name) {
+            Some(window) => window,
+            None => panic!("frame {} has no window {name:?}, its windows are {:?}", self.index, self.window_names()),
+        }
+    }
+
+    #[track_caller]
+    pub fn assert_no_window(&self, name: &str) {
+        if self.window(name).is_some() {
+            panic!("frame {} has a window {name:?}", self.index)
+        }
+    }
+
+    /// Panics, showing the frame's text, if no item shows `text`.
+    #[track_caller]
+    pub fn assert_text_contains(&self, text: &str) {
+        if !self.text.contains(text) {
+            panic!("frame {} doesn't show {text:?}, it shows:\n{}", self.index, self.text)
+        }
+    }
+
+    /// Compares the frame's whole text with `expected`, ignoring spaces at the start and end of lines,
+    /// and panics with a line diff if they differ.
+    #[track_caller]
+    pub fn assert_text(&self, expected: &str) {
+        let normalize = |text: &str| text.lines().map(str::trim).filter(|line| !line.is_empty()).collect::<Vec<_>>().join("\n");
+        let (expected, actual) = (normalize(expected), normalize(&self.text));
+        if expected != actual {
+            panic!("frame {} doesn't show the expected text (-expected +actual):\n{}", self.index, diff::lines(&expected, &actual))
+        }
+    }
+}
+
+/// Reads the windows submitted this frame from imgui's internal state.
+///
+/// # Safety
+/// Must be called after the frame is rendered and before the next one starts.
+pub(crate) unsafe fn submitted_windows() -> Vec<WindowInfo> {
+    let context = &*imgui::sys::igGetCurrentContext();
+    let glyphs = glyph_table(context);
+
+    slice(context.Windows.Data, context.Windows.Size).iter()
+        .map(|&window| &*window)
+        .filter(|window| window.Active)
+        .map(|window| WindowInfo {
+            name: CStr::from_ptr(window.Name).to_string_lossy().into_owned(),
+            pos: [window.Pos.x, window.Pos.y],
+            size: [window.Size.x, window.Size.y],
+            collapsed: window.Collapsed,
+            hidden: window.Hidden,
+            focused: std::ptr::eq(context.NavWindow, window),
+            child: window.Flags & imgui::sys::ImGuiWindowFlags_ChildWindow as i32 != 0,
+            text: {
+                let draw_list = &*window.DrawList;
+                draw_list_text(slice(draw_list.VtxBuffer.Data.cast::<DrawVert>(), draw_list.VtxBuffer.Size), &glyphs)
+            },
+        })
+        .collect()
+}
+
+/// Where each glyph of the loaded fonts is in the font atlas, to recognize them in draw lists,
+/// by the texture coordinates of their top left corner and of their bottom right corner.
+struct GlyphTable<'a> {
+    by_top_left: HashMap<(u32, u32), &'a FontGlyph>,
+    by_bottom_right: HashMap<(u32, u32), &'a FontGlyph>,
+}
+
+unsafe fn glyph_table(context: &imgui::sys::ImGuiContext) -> GlyphTable<'_> {
+    let atlas = &*context.IO.Fonts;
+    let mut glyphs = GlyphTable { by_top_left: HashMap::new(), by_bottom_right: HashMap::new() };
+    for &font in slice(atlas.Fonts.Data, atlas.Fonts.Size) {
+        let font = &*font;
+        for glyph in slice(font.Glyphs.Data.cast::<FontGlyph>(), font.Glyphs.Size) {
+            glyphs.by_top_left.insert(uv_key([glyph.u0, glyph.v0]), glyph);
+            glyphs.by_bottom_right.insert(uv_key([glyph.u1, glyph.v1]), glyph);
+        }
+    }
+
+    glyphs
+}
+
+fn uv_key(uv: [f32; 2]) -> (u32, u32) {
+    (uv[0].to_bits(), uv[1].to_bits())
+}
+
+impl GlyphTable<'_> {
+    /// The glyph a quad with these texture coordinates draws. A glyph that crosses the edge of its clip rectangle
+    /// is cut along with its texture coordinates, so only one of its corners is still where the glyph's is.
+    fn find(&self, top_left: [f32; 2], bottom_right: [f32; 2]) -> Option<&FontGlyph> {
+        let contains = |glyph: &FontGlyph, uv: [f32; 2]| {
+            (glyph.u0..=glyph.u1).contains(&uv[0]) && (glyph.v0..=glyph.v1).contains(&uv[1])
+        };
+        self.by_top_left.get(&uv_key(top_left)).filter(|glyph| contains(glyph, bottom_right))
+            .or_else(|| self.by_bottom_right.get(&uv_key(bottom_right)).filter(|glyph| contains(glyph, top_left)))
+            .copied()
+    }
+}
+
+/// Reads back the text of a draw list from its glyph quads.
+///
+/// A glyph is 4 vertices, from the top left going clockwise, textured with its place in the atlas.
+/// A new line starts when the glyphs move to another line, and glyphs that aren't next to each other
+/// are separated by a single space, whether it's a space character or the gap between two items.
+/// Glyphs cut by a clip rectangle are kept, whole.
+fn draw_list_text(vertices: &[DrawVert], glyphs: &GlyphTable) -> String {
+    let mut text = String::new();
+    // Where the current line is and where its last glyph ends
+    let mut line: Option<(f32, f32)> = None;
+    let mut i = 0;
+    while i + 3 < vertices.len() {
+        let [top_left, bottom_right] = [&vertices[i], &vertices[i + 2]];
+        let Some(glyph) = glyphs.find(top_left.uv, bottom_right.uv) else {
+            i += 1;
+            continue
+        };
+        // Fonts can be scaled, and cutting a glyph cuts its quad and its texture coordinates alike
+        let pos_per_uv = |axis: usize| match bottom_right.uv[axis] - top_left.uv[axis] {
+            drawn if drawn > 0.0 => (bottom_right.pos[axis] - top_left.pos[axis]) / drawn,
+            _ => 0.0,
+        };
+        let scale = match glyph.x1 - glyph.x0 {
+            width if width > 0.0 => pos_per_uv(0) * (glyph.u1 - glyph.u0) / width,
+            _ => 1.0,
+        };
+        // Where the glyph's top left corner is, even if it's cut off
+        let corner = [
+            top_left.pos[0] - (top_left.uv[0] - glyph.u0) * pos_per_uv(0),
+            top_left.pos[1] - (top_left.uv[1] - glyph.v0) * pos_per_uv(1),
+        ];
+        let origin = [corner[0] - glyph.x0 * scale, corner[1] - glyph.y0 * scale];
+        match line {
+            Some((y, end)) if (origin[1] - y).abs() < 1.0 && (origin[0] - end).abs() > 1.0 => text.push(' '),
+            Some((y, _)) if (origin[1] - y).abs() < 1.0 => {}
+            Some(_) => text.push('\n'),
+            None => {}
+        }
+        text.extend(char::from_u32(glyph.codepoint()));
+        line = Some((origin[1], origin[0] + glyph.advance_x * scale));
+        i += 4;
+    }
+
+    text
+}
+
+unsafe fn slice<'a, T>(data: *const T, len: i32) -> &'a [T] {
+    match len {
+        0 => &[],
+        len => std::slice::from_raw_parts(data, len as usize),
+    }
+}
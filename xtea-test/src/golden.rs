@@ -0,0 +1,85 @@
+//! Compares screenshots with golden images, the screenshots a test is known to produce.
+
+use std::path::{Path, PathBuf};
+
+use image::{Rgba, RgbaImage};
+
+/// Set this environment variable to write the screenshots as the new golden images instead of comparing them.
+pub const UPDATE_GOLDEN_VAR: &str = "XTEA_UPDATE_GOLDEN";
+
+/// How far apart a channel can be, for rounding differences between machines.
+const TOLERANCE: u8 = 2;
+
+/// Panics if `actual` differs from the image at `golden`. The screenshot and an image highlighting
+/// the differences are then saved next to it, as `<name>.actual.png` and `<name>.diff.png`.
+#[track_caller]
+pub(crate) fn assert_matches(actual: &RgbaImage, golden: &Path) {
+    if std::env::var_os(UPDATE_GOLDEN_VAR).is_some() {
+        save(actual, golden.to_path_buf());
+        return
+    }
+
+    let expected = match image::open(golden) {
+        Ok(expected) => expected.to_rgba8(),
+        Err(e) => {
+            let actual_path = save(actual, sibling(golden, "actual"));
+            panic!(
+                "could not read the golden image {}: {e}\nthe screenshot is saved as {}, run the test with {UPDATE_GOLDEN_VAR}=1 to make it the golden image",
+                golden.display(), actual_path.display()
+            )
+        },
+    };
+    if expected.dimensions() != actual.dimensions() {
+        let actual_path = save(actual, sibling(golden, "actual"));
+        panic!(
+            "the screenshot is {}x{} but the golden image {} is {}x{}, the screenshot is saved as {}",
+            actual.width(), actual.height(), golden.display(), expected.width(), expected.height(), actual_path.display()
+        )
+    }
+
+    // Differing pixels in red, over a faded copy of the golden image
+    let mut diff = RgbaImage::new(actual.width(), actual.height());
+    let mut differing = 0;
+    let mut bounds = [u32::MAX, u32::MAX, 0, 0];
+    for (x, y, expected_pixel) in expected.enumerate_pixels() {
+        let actual_pixel = actual.get_pixel(x, y);
+        let differs = expected_pixel.0.iter().zip(actual_pixel.0).any(|(&expected, actual)| expected.abs_diff(actual) > TOLERANCE);
+        if differs {
+            differing += 1;
+            bounds = [bounds[0].min(x), bounds[1].min(y), bounds[2].max(x), bounds[3].max(y)];
+            diff.put_pixel(x, y, Rgba([255, 0, 0, 255]))
+        } else {
+            let [r, g, b, _] = expected_pixel.0;
+            let gray = ((r as u32 + g as u32 + b as u32) / 3 / 4) as u8;
+            diff.put_pixel(x, y, Rgba([gray, gray, gray, 255]))
+        }
+    }
+
+    if differing > 0 {
+        let actual_path = save(actual, sibling(golden, "actual"));
+        let diff_path = save(&diff, sibling(golden, "diff"));
+        panic!(
+            "the screenshot differs from the golden image {} in {differing} pixels, from ({}, {}) to ({}, {})\n\
+            the screenshot is saved as {} and the differences as {}, run the test with {UPDATE_GOLDEN_VAR}=1 to accept it",
+            golden.display(), bounds[0], bounds[1], bounds[2], bounds[3], actual_path.display(), diff_path.display()
+        )
+    }
+}
+
+/// `dir/name.png` becomes `dir/name.<suffix>.png`.
+fn sibling(golden: &Path, suffix: &str) -> PathBuf {
+    let stem = golden.file_stem().unwrap_or_default().to_string_lossy();
+    golden.with_file_name(format!("{stem}.{suffix}.png"))
+}
+
+fn save(image: &RgbaImage, path: PathBuf) -> PathBuf {
+    if let Some(dir) = path.parent() {
+        // Saving fails right after otherwise
+        let _ = std::fs::create_dir_all(dir);
+    }
+    if let Err(e) = image.save(&path) {
+        panic!("could not save {}: {e}", path.display())
+    }
+
+    path
+}
@@ -0,0 +1,363 @@
+//! Runs an xtea plugin without a window or a GPU, so it can be tested in CI.
+//!
+//! The [`Harness`] gives the plugin an imgui context like xtea does, creates its instance, and runs
+//! frames on demand. Each [`Frame`] keeps the text drawn in each window, the windows, the draw data and the logs,
+//! and input (mouse, keys, text, or activating an item by its ID) is given to imgui before the next frame.
+//!
+//! ```ignore
+//! use xtea_test::Harness;
+//!
+//! #[test]
+//! fn shows_a_window() {
+//!     // A plugin library, built by cargo next to the test
+//!     let mut harness = Harness::load(xtea_test::library_path("my-plugin")).unwrap();
+//!     // Or a plugin linked into the test: Harness::new::<MyPlugin>()
+//!
+//!     harness.run_frames(2).assert_window("My Window");
+//!     harness.activate(&["My Window", "Reset"]);
+//!     // Pressed in the first frame, shown in the second
+//!     let frame = harness.run_frames(2);
+//!     frame.assert_text_contains("Counter: 0");
+//!     harness.assert_logged(log::Level::Info, "reset");
+//! }
+//! ```
+//!
+//! Screenshots are drawn on the CPU by the [`Rasterizer`], so they can be compared with golden images
+//! with [`Harness::assert_screenshot`] on machines without a GPU.
+//!
+//! imgui has a single global context, so harnesses wait for each other: tests run one harness at a time.
+
+use std::{
+    error::Error,
+    fmt::Display,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard, PoisonError},
+};
+
+use image::RgbaImage;
+use imgui::{Context, Key, MouseButton};
+use log::Level;
+use xtealib::{plugin::PluginVTable, XteaPlugin};
+pub use xtealib::LogRecord;
+
+use self::plugin::TestPlugin;
+pub use self::{
+    frame::{Frame, WindowInfo},
+    golden::UPDATE_GOLDEN_VAR,
+};
+pub use xtea_raster::{DrawCommand, DrawData, DrawList, Rasterizer, FONT_TEXTURE};
+
+mod diff;
+mod frame;
+mod golden;
+mod logs;
+mod plugin;
+
+/// Size of the fake screen, a common laptop resolution.
+pub const DEFAULT_DISPLAY_SIZE: [f32; 2] = [1280.0, 720.0];
+/// Frames are 60 per second, whatever time they take to run.
+pub const DEFAULT_DELTA_TIME: f32 = 1.0 / 60.0;
+
+static HARNESS_LOCK: Mutex<()> = Mutex::new(());
+
+/// Why a plugin couldn't be started.
+#[derive(Debug)]
+pub enum HarnessError {
+    /// Its library couldn't be opened, or it isn't an xtea plugin built like this harness.
+    Load(String),
+    /// Its `new` returned an error.
+    Init(String),
+    /// It panicked, with the given report.
+    Panic(String),
+}
+
+impl Display for HarnessError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HarnessError::Load(reason) => write!(f, "could not load the plugin: {reason}"),
+            HarnessError::Init(reason) => write!(f, "the plugin failed to initialize: {reason}"),
+            HarnessError::Panic(report) => write!(f, "the plugin panicked: {report}"),
+        }
+    }
+}
+
+impl Error for HarnessError {}
+
+/// Where cargo puts the library of the plugin crate `crate_name`, when it builds it for the same profile as the test.
+pub fn library_path(crate_name: &str) -> PathBuf {
+    let file_name = format!(
+        "{}{}{}", std::env::consts::DLL_PREFIX, crate_name.replace('-', "_"), std::env::consts::DLL_SUFFIX
+    );
+    // Tests run from target/<profile>/deps, where `cargo test` leaves the libraries of the package under test,
+    // the libraries of other packages are in target/<profile>
+    let exe = std::env::current_exe().unwrap_or_default();
+    let deps_dir = exe.parent().unwrap_or(Path::new("."));
+    let in_deps = deps_dir.join(&file_name);
+    if in_deps.exists() {
+        return in_deps
+    }
+
+    deps_dir.parent().unwrap_or(deps_dir).join(file_name)
+}
+
+/// A plugin running in an imgui context without a window.
+pub struct Harness {
+    /// Only `None` while it's dropped, the plugin goes before the context.
+    plugin: Option<TestPlugin>,
+    name: String,
+    context: Context,
+    rasterizer: Rasterizer,
+    frame_index: usize,
+    last_frame: Option<Frame>,
+    logs: Vec<LogRecord>,
+    view_menu: bool,
+    delta_time: f32,
+    // Last, released once the context is destroyed
+    _lock: MutexGuard<'static, ()>,
+}
+
+impl Harness {
+    /// Starts a plugin that is linked into the test.
+    pub fn new<T: XteaPlugin>() -> Result<Harness, HarnessError> {
+        Harness::from_vtable(PluginVTable::for_plugin::<T>())
+    }
+
+    /// Starts a plugin that is linked into the test, from its vtable,
+    /// like `PluginVTable::for_serde_plugin::<MyPlugin>()`.
+    pub fn from_vtable(vtable: PluginVTable) -> Result<Harness, HarnessError> {
+        Harness::start(|| Ok(TestPlugin::from_vtable(vtable)))
+    }
+
+    /// Loads a plugin library and starts it.
+    pub fn load(library: impl AsRef<Path>) -> Result<Harness, HarnessError> {
+        Harness::start(|| TestPlugin::load(library.as_ref()))
+    }
+
+    fn start(plugin: impl FnOnce() -> Result<TestPlugin, HarnessError>) -> Result<Harness, HarnessError> {
+        let lock = HARNESS_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+        logs::install();
+        // Records of whatever ran between two harnesses
+        drop(logs::take());
+
+        let mut context = Context::create();
+        context.set_ini_filename(None);
+        context.set_log_filename(None);
+        context.io_mut().display_size = DEFAULT_DISPLAY_SIZE;
+        context.io_mut().config_flags |= imgui::ConfigFlags::DOCKING_ENABLE;
+        let rasterizer = Rasterizer::new(&mut context);
+
+        let mut plugin = plugin()?;
+        plugin.init()?;
+
+        Ok(Harness {
+            name: plugin.name(),
+            plugin: Some(plugin),
+            context,
+            rasterizer,
+            frame_index: 0,
+            last_frame: None,
+            logs: logs::take(),
+            view_menu: false,
+            delta_time: DEFAULT_DELTA_TIME,
+            _lock: lock,
+        })
+    }
+
+    /// The name the plugin gives itself.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn set_display_size(&mut self, display_size: [f32; 2]) {
+        self.context.io_mut().display_size = display_size
+    }
+
+    pub fn set_delta_time(&mut self, delta_time: f32) {
+        self.delta_time = delta_time
+    }
+
+    /// Also runs the plugin's "View" menu entries every frame, in a window called "View".
+    pub fn set_view_menu(&mut self, view_menu: bool) {
+        self.view_menu = view_menu
+    }
+
+    /// Runs one frame and returns what it produced.
+    ///
+    /// Panics with the plugin's report if it panics.
+    pub fn run_frame(&mut self) -> &Frame {
+        let plugin = self.plugin.as_mut().expect("the plugin is only taken when dropped");
+        let view_menu = self.view_menu;
+        self.context.io_mut().delta_time = self.delta_time;
+
+        let ui = self.context.new_frame();
+        plugin.build_ui(ui);
+        if view_menu {
+            ui.window("View").build(|| plugin.view_submenu(ui));
+        }
+        let draw_data = DrawData::capture(self.context.render());
+        let windows = unsafe { frame::submitted_windows() };
+
+        if let Some(report) = plugin.take_panic() {
+            panic!("plugin {} panicked in frame {}:\n{report}", self.name, self.frame_index)
+        }
+
+        let text = windows.iter()
+            .filter(|window| !window.text.is_empty())
+            .map(|window| window.text.as_str())
+            .collect::<Vec<_>>()
+            .join("\n");
+        let logs = logs::take();
+        self.logs.extend(logs.iter().cloned());
+        let frame = Frame { index: self.frame_index, text, windows, draw_data, logs };
+        self.frame_index += 1;
+
+        self.last_frame.insert(frame)
+    }
+
+    /// Runs `count` frames and returns the last one. Input takes a few frames to be processed.
+    pub fn run_frames(&mut self, count: usize) -> &Frame {
+        assert!(count > 0, "run at least one frame");
+        for _ in 1..count {
+            self.run_frame();
+        }
+
+        self.run_frame()
+    }
+
+    /// The last frame that ran.
+    pub fn last_frame(&self) -> Option<&Frame> {
+        self.last_frame.as_ref()
+    }
+
+    /// Draws the last frame.
+    pub fn screenshot(&self) -> RgbaImage {
+        let frame = self.last_frame.as_ref().expect("run a frame before taking a screenshot");
+        self.rasterizer.render(&frame.draw_data)
+    }
+
+    /// Panics if the last frame doesn't look like the golden image at `golden`, see [`UPDATE_GOLDEN_VAR`]
+    /// to create or update it. Panels and windows that show times or counters make unstable screenshots.
+    #[track_caller]
+    pub fn assert_screenshot(&self, golden: impl AsRef<Path>) {
+        golden::assert_matches(&self.screenshot(), golden.as_ref())
+    }
+
+    /// To draw the images the plugin shows.
+    pub fn rasterizer_mut(&mut self) -> &mut Rasterizer {
+        &mut self.rasterizer
+    }
+
+    /// Offers a file to the plugin, like one given on xtea's command line. Returns whether it opened it.
+    pub fn open_file(&mut self, path: impl AsRef<Path>) -> bool {
+        let plugin = self.plugin.as_mut().expect("the plugin is only taken when dropped");
+        let opened = plugin.open_file(path.as_ref());
+        if let Some(report) = plugin.take_panic() {
+            panic!("plugin {} panicked opening {}:\n{report}", self.name, path.as_ref().display())
+        }
+
+        opened
+    }
+
+    // Input is queued like a real backend does, imgui trickles it over the next frames
+
+    pub fn mouse_move(&mut self, pos: [f32; 2]) {
+        self.context.io_mut().add_mouse_pos_event(pos)
+    }
+
+    pub fn mouse_button(&mut self, button: MouseButton, down: bool) {
+        self.context.io_mut().add_mouse_button_event(button, down)
+    }
+
+    /// Moves the mouse to `pos` and clicks the left button. Takes 3 frames.
+    pub fn click(&mut self, pos: [f32; 2]) {
+        self.mouse_move(pos);
+        self.mouse_button(MouseButton::Left, true);
+        self.mouse_button(MouseButton::Left, false);
+    }
+
+    pub fn scroll(&mut self, wheel: [f32; 2]) {
+        self.context.io_mut().add_mouse_wheel_event(wheel)
+    }
+
+    pub fn key(&mut self, key: Key, down: bool) {
+        self.context.io_mut().add_key_event(key, down)
+    }
+
+    /// Presses and releases `key`. Takes 2 frames.
+    pub fn press_key(&mut self, key: Key) {
+        self.key(key, true);
+        self.key(key, false);
+    }
+
+    /// Types `text` into the focused text input.
+    pub fn type_text(&mut self, text: &str) {
+        for character in text.chars() {
+            self.context.io_mut().add_input_character(character)
+        }
+    }
+
+    /// Presses the item at `path` during the next frame, wherever it is, like keyboard navigation would.
+    /// What it changes is usually only drawn in the frame after. See [`item_id`].
+    pub fn activate(&mut self, path: &[&str]) {
+        unsafe { imgui::sys::igActivateItem(item_id(path)) }
+    }
+
+    /// Every log record since the harness was created, including the plugin's initialization.
+    pub fn logs(&self) -> &[LogRecord] {
+        &self.logs
+    }
+
+    /// Panics, listing every record, if no record at `level` contains `message`.
+    #[track_caller]
+    pub fn assert_logged(&self, level: Level, message: &str) {
+        if !self.logs.iter().any(|record| record.level == level && record.message.contains(message)) {
+            panic!("nothing containing {message:?} was logged at {level}, the logs are:\n{}", self.logs_text())
+        }
+    }
+
+    /// Panics, listing every record, if something was logged at `Warn` or `Error`.
+    #[track_caller]
+    pub fn assert_no_warnings(&self) {
+        if self.logs.iter().any(|record| record.level <= Level::Warn) {
+            panic!("warnings or errors were logged:\n{}", self.logs_text())
+        }
+    }
+
+    fn logs_text(&self) -> String {
+        self.logs.iter().map(|record| format!("    [{}] {record}\n", record.target)).collect()
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        // Shuts the plugin down while the context is still alive
+        drop(self.plugin.take());
+    }
+}
+
+/// The imgui ID of an item: the window name, then every `push_id` around the item, then its label.
+///
+/// For `ui.window("Settings").build(|| { let _id = ui.push_id("row 3"); ui.button("Delete") })`
+/// it's `item_id(&["Settings", "row 3", "Delete"])`.
+pub fn item_id(path: &[&str]) -> u32 {
+    path.iter().fold(0, |seed, part| unsafe { imgui::sys::igImHashStr(part.as_ptr().cast(), part.len(), seed) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::item_id;
+
+    #[test]
+    fn item_ids_depend_on_the_whole_path() {
+        assert_ne!(item_id(&["Window", "Button"]), item_id(&["Other", "Button"]));
+        assert_ne!(item_id(&["Window", "a", "Button"]), item_id(&["Window", "b", "Button"]));
+        assert_eq!(item_id(&["Window", "Button"]), item_id(&["Window", "Button"]));
+    }
+
+    #[test]
+    fn only_the_part_after_triple_hashes_counts() {
+        // Like imgui, so a label can change without changing its ID
+        assert_eq!(item_id(&["Window", "Save 3 files###save"]), item_id(&["Window", "Save 1 file###save"]));
+        assert_ne!(item_id(&["Window", "Save##a"]), item_id(&["Window", "Save##b"]));
+    }
+}
@@ -0,0 +1,70 @@
+//! Captures the log records of the plugin under test.
+
+use std::{sync::{Arc, Mutex, Once, RwLock}, time::SystemTime};
+
+use log::{Log, Metadata, Record};
+use xtealib::{plugin::{FfiLogRecord, FfiRecord}, LogRecord};
+
+/// Only one harness runs at a time, so there's a single capture.
+static CAPTURED: Mutex<Vec<LogRecord>> = Mutex::new(Vec::new());
+/// The name of the plugin under test, for the records it sends.
+static PLUGIN: RwLock<Option<Arc<str>>> = RwLock::new(None);
+static INSTALL: Once = Once::new();
+
+/// Captures the records of the test itself and of plugins linked into it.
+struct CaptureLogger;
+
+impl Log for CaptureLogger {
+    fn enabled(&self, _metadata: &Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &Record) {
+        push(LogRecord::new(record))
+    }
+
+    fn flush(&self) {}
+}
+
+/// Becomes the global logger, unless the test already set one.
+pub(crate) fn install() {
+    INSTALL.call_once(|| {
+        if log::set_logger(&CaptureLogger).is_ok() {
+            log::set_max_level(log::LevelFilter::Trace)
+        }
+    })
+}
+
+pub(crate) fn set_plugin(name: String) {
+    *PLUGIN.write().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(name.into())
+}
+
+/// Receives the records of plugin libraries.
+pub(crate) extern "C" fn capture_ffi_record(record: &FfiLogRecord) {
+    let plugin = PLUGIN.read().unwrap_or_else(|poisoned| poisoned.into_inner()).clone();
+    push(unsafe { LogRecord::from_ffi(record, plugin) })
+}
+
+/// Receives the records of plugin libraries built with an older xtealib.
+pub(crate) extern "C" fn capture_ffi(record: &FfiRecord) {
+    push(LogRecord {
+        level: record.level(),
+        timestamp: SystemTime::now(),
+        target: unsafe { record.target.as_str() }.to_string(),
+        plugin: None,
+        module_path: None,
+        file: None,
+        line: None,
+        message: unsafe { record.message.as_str() }.to_string(),
+        fields: Vec::new(),
+    })
+}
+
+fn push(record: LogRecord) {
+    CAPTURED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).push(record)
+}
+
+/// The records captured since the last call.
+pub(crate) fn take() -> Vec<LogRecord> {
+    std::mem::take(&mut *CAPTURED.lock().unwrap_or_else(|poisoned| poisoned.into_inner()))
+}
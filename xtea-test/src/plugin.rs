@@ -0,0 +1,109 @@
+use std::{path::Path, ptr::NonNull, time::Duration};
+
+use dlopen::raw::Library;
+use imgui::Ui;
+use xtealib::{
+    abi::ABI_SYMBOL,
+    ffi::{FfiStr, UiHandle},
+    host,
+    plugin::{ByteSink, HostApi, PluginInstance, PluginVTable, ENTRY_POINT_SYMBOL},
+};
+
+use crate::{logs, HarnessError};
+
+/// How long the plugin has to stop its tasks when the harness is dropped, like in xtea.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A plugin driven by the harness, the same way xtea drives it.
+pub(crate) struct TestPlugin {
+    vtable: PluginVTable,
+    instance: Option<NonNull<PluginInstance>>,
+    /// The plugin panicked, its instance can only be destroyed.
+    crashed: bool,
+    /// `None` for plugins linked into the test.
+    library: Option<Library>,
+}
+
+impl TestPlugin {
+    pub fn from_vtable(vtable: PluginVTable) -> TestPlugin {
+        TestPlugin { vtable, instance: None, crashed: false, library: None }
+    }
+
+    /// Opens a plugin library, with the checks xtea does.
+    pub fn load(path: &Path) -> Result<TestPlugin, HarnessError> {
+        let library = Library::open(path).map_err(|e| HarnessError::Load(format!("{}: {e}", path.display())))?;
+
+        let (vtable, _) = unsafe {
+            host::read_plugin(library.symbol(ABI_SYMBOL).ok(), library.symbol(ENTRY_POINT_SYMBOL).ok())
+        }.map_err(|reason| HarnessError::Load(format!("{}: {reason}", path.display())))?;
+
+        Ok(TestPlugin { vtable, instance: None, crashed: false, library: Some(library) })
+    }
+
+    pub fn name(&self) -> String {
+        unsafe { (self.vtable.get_name)().as_str() }.to_string()
+    }
+
+    /// Connects the plugin to the current imgui context and to the log capture, then creates its instance.
+    pub fn init(&mut self) -> Result<(), HarnessError> {
+        let host = HostApi::new(log::LevelFilter::Trace, logs::capture_ffi, 0, logs::capture_ffi_record);
+        logs::set_plugin(self.name());
+        (self.vtable.connect_host)(&host);
+
+        let mut error = Vec::new();
+        let instance = match self.vtable.try_create {
+            Some(try_create) => try_create(&mut ByteSink::for_vec(&mut error)),
+            None => (self.vtable.create)(),
+        };
+        if let Some(report) = self.take_panic() {
+            return Err(HarnessError::Panic(report))
+        }
+        self.instance = NonNull::new(instance);
+        match (self.instance, error.is_empty()) {
+            (Some(_), _) => Ok(()),
+            (None, false) => Err(HarnessError::Init(String::from_utf8_lossy(&error).into_owned())),
+            (None, true) => Err(HarnessError::Init("it didn't create its instance".to_string())),
+        }
+    }
+
+    pub fn build_ui(&mut self, ui: &Ui) {
+        if let (Some(instance), Some(build_ui)) = (self.instance, self.vtable.build_ui) {
+            build_ui(instance.as_ptr(), UiHandle::new(ui))
+        }
+    }
+
+    pub fn view_submenu(&mut self, ui: &Ui) {
+        if let (Some(instance), Some(view_submenu)) = (self.instance, self.vtable.view_submenu) {
+            view_submenu(instance.as_ptr(), UiHandle::new(ui))
+        }
+    }
+
+    pub fn open_file(&mut self, path: &Path) -> bool {
+        let (Some(instance), Some(open_file)) = (self.instance, self.vtable.open_file) else {
+            return false
+        };
+        let path = path.to_string_lossy();
+        open_file(instance.as_ptr(), FfiStr::new(&path))
+    }
+
+    pub fn take_panic(&mut self) -> Option<String> {
+        let take_panic = self.vtable.take_panic?;
+        let mut report = Vec::new();
+        take_panic(&mut ByteSink::for_vec(&mut report));
+        if report.is_empty() {
+            return None
+        }
+
+        self.crashed = true;
+        Some(String::from_utf8_lossy(&report).into_owned())
+    }
+}
+
+impl Drop for TestPlugin {
+    fn drop(&mut self) {
+        if host::shutdown(&self.vtable, self.instance.take(), self.crashed, SHUTDOWN_TIMEOUT) > 0 {
+            log::error!("Plugin {} didn't stop its tasks, its library is leaked", self.name());
+            std::mem::forget(self.library.take());
+        }
+    }
+}
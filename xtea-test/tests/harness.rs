@@ -0,0 +1,170 @@
+use std::{error::Error, sync::atomic::{AtomicU32, Ordering}};
+
+use imgui::Ui;
+use log::Level;
+use xtea_test::{item_id, Harness, HarnessError};
+use xtealib::XteaPlugin;
+
+/// The ID imgui gave to the "Add" button in the last frame.
+static ADD_ID: AtomicU32 = AtomicU32::new(0);
+
+struct Counter {
+    count: u32,
+}
+
+impl XteaPlugin for Counter {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        log::info!("created");
+        Ok(Counter { count: 0 })
+    }
+
+    fn name() -> &'static str {
+        "Counter"
+    }
+
+    fn build_ui(&mut self, ui: &Ui) {
+        ui.window("Counter").build(|| {
+            ui.text(format!("Count: {}", self.count));
+            let _id = ui.push_id("buttons");
+            if ui.button("Add") {
+                self.count += 1;
+                log::info!("added")
+            }
+            ADD_ID.store(unsafe { imgui::sys::igGetItemID() }, Ordering::SeqCst);
+            ui.same_line();
+            ui.text("clicks");
+        });
+        ui.window("Narrow").size([63.0, 40.0], imgui::Condition::Always).build(|| ui.text("abcdefghijklmnop"));
+    }
+
+    fn view_submenu(&mut self, ui: &Ui) {
+        ui.text("Counter options")
+    }
+}
+
+struct Failing;
+
+impl XteaPlugin for Failing {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Err("no config".into())
+    }
+
+    fn name() -> &'static str {
+        "Failing"
+    }
+
+    fn build_ui(&mut self, _ui: &Ui) {}
+}
+
+struct Panicking;
+
+impl XteaPlugin for Panicking {
+    fn new() -> Result<Self, Box<dyn Error>> {
+        Ok(Panicking)
+    }
+
+    fn name() -> &'static str {
+        "Panicking"
+    }
+
+    fn build_ui(&mut self, _ui: &Ui) {
+        panic!("oops")
+    }
+}
+
+#[test]
+fn reads_the_text_back_from_the_glyphs() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    let frame = harness.run_frames(2);
+
+    // Items on the same line are separated by a space
+    assert_eq!(frame.assert_window("Counter").text, "Counter\nCount: 0\nAdd clicks");
+    frame.assert_text_contains("Count: 0");
+}
+
+#[test]
+fn glyphs_cut_by_the_window_edge_are_kept() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+
+    // The window's content ends in the middle of the "f"
+    assert_eq!(harness.run_frames(2).assert_window("Narrow").text, "Narrow\nabcdef");
+}
+
+#[test]
+fn item_ids_are_imguis() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    harness.run_frame();
+
+    assert_eq!(item_id(&["Counter", "buttons", "Add"]), ADD_ID.load(Ordering::SeqCst))
+}
+
+#[test]
+fn activating_an_item_presses_it() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    harness.run_frame();
+
+    harness.activate(&["Counter", "buttons", "Add"]);
+    harness.run_frames(2).assert_text_contains("Count: 1");
+    harness.assert_logged(Level::Info, "added");
+    harness.assert_no_warnings();
+}
+
+#[test]
+fn clicking_an_item_presses_it() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    harness.set_display_size([400.0, 300.0]);
+    harness.run_frames(2);
+
+    // Where the default style puts the button in a window at (60, 60)
+    let window = harness.last_frame().unwrap().assert_window("Counter").clone();
+    harness.click([window.pos[0] + 20.0, window.pos[1] + 50.0]);
+    harness.run_frames(3).assert_text_contains("Count: 1");
+}
+
+#[test]
+fn logs_include_initialization() {
+    let harness = Harness::new::<Counter>().unwrap();
+
+    harness.assert_logged(Level::Info, "created");
+    let record = &harness.logs()[0];
+    assert_eq!(record.plugin, None);
+    assert_eq!(record.target, "harness");
+}
+
+#[test]
+fn the_view_menu_is_shown_in_its_own_window() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    harness.run_frames(2).assert_no_window("View");
+
+    harness.set_view_menu(true);
+    harness.run_frames(2).assert_window("View");
+    harness.last_frame().unwrap().assert_text_contains("Counter options");
+}
+
+#[test]
+#[should_panic(expected = "- Count: 5\n+ Count: 0")]
+fn text_assertions_show_a_diff() {
+    let mut harness = Harness::new::<Counter>().unwrap();
+    harness.run_frames(2).assert_text("Counter\nCount: 5\nAdd clicks\nNarrow\nabcdef");
+}
+
+#[test]
+fn initialization_errors_are_returned() {
+    match Harness::new::<Failing>() {
+        Err(HarnessError::Init(reason)) => assert_eq!(reason, "no config"),
+        Err(e) => panic!("unexpected error: {e}"),
+        Ok(_) => panic!("the plugin started"),
+    }
+}
+
+#[test]
+#[should_panic(expected = "plugin Panicking panicked in frame 0")]
+fn plugin_panics_fail_the_test() {
+    let mut harness = Harness::new::<Panicking>().unwrap();
+    harness.run_frame();
+}
+
+#[test]
+fn missing_libraries_are_load_errors() {
+    assert!(matches!(Harness::load("does/not/exist.so"), Err(HarnessError::Load(_))))
+}
@@ -0,0 +1,249 @@
+use std::{ffi::OsString, path::PathBuf, str::FromStr};
+
+use log::LevelFilter;
+use xtealib::LogFilter;
+
+use crate::plugins::LoadFilter;
+
+pub const USAGE: &str = "\
+Usage: xtea [OPTIONS] [FILE]...
+
+Opens each FILE with the first plugin that can open it.
+
+Options:
+      --plugin-dir <DIR>       Also search plugins in DIR, can be repeated
+      --only <PLUGINS>         Only load these plugins, by name, ID or library name, separated by commas
+      --disable <PLUGINS>      Don't load these plugins, separated by commas
+      --safe-mode              Don't load any plugin
+      --list-plugins           Print the plugins found and exit
+      --config <FILE>          Read and save the config in FILE instead of the user config directory
+      --log-stderr             Also print the logs to stderr
+      --log <FILTER>           Which logs to keep, like RUST_LOG: info,my_plugin=debug,wgpu=off [env: XTEA_LOG]
+      --log-level <LEVEL>      Level of the targets the log filter doesn't name: off, error, warn, info, debug or trace
+      --geometry <GEOMETRY>    Initial window size and position, as WIDTHxHEIGHT or WIDTHxHEIGHT+X+Y
+      --screenshot <FILE>      Draw xtea into the PNG FILE without a window or a GPU, once the plugins are initialized, and exit
+      --frames <N>             Frames to run before the screenshot [default: 3]
+  -h, --help                   Print this help and exit
+  -V, --version                Print the version and exit";
+
+/// The command line arguments of xtea.
+#[derive(Debug)]
+pub struct Args {
+    pub plugin_dirs: Vec<PathBuf>,
+    pub load_filter: LoadFilter,
+    pub list_plugins: bool,
+    pub config: Option<PathBuf>,
+    pub log_stderr: bool,
+    /// Replaces the filter of the config.
+    pub log_filter: Option<LogFilter>,
+    /// Replaces the default level of the log filter.
+    pub log_level: Option<LevelFilter>,
+    pub geometry: Option<Geometry>,
+    pub screenshot: Option<PathBuf>,
+    pub frames: usize,
+    pub files: Vec<PathBuf>,
+    pub help: bool,
+    pub version: bool,
+}
+
+impl Default for Args {
+    fn default() -> Self {
+        Args {
+            plugin_dirs: Vec::new(),
+            load_filter: LoadFilter::default(),
+            list_plugins: false,
+            config: None,
+            log_stderr: false,
+            log_filter: None,
+            log_level: None,
+            geometry: None,
+            screenshot: None,
+            frames: 3,
+            files: Vec::new(),
+            help: false,
+            version: false,
+        }
+    }
+}
+
+/// Initial size and, optionally, position of the window, in logical pixels.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Geometry {
+    pub width: u32,
+    pub height: u32,
+    pub position: Option<(i32, i32)>,
+}
+
+impl FromStr for Geometry {
+    type Err = String;
+
+    /// Parses `WIDTHxHEIGHT` or `WIDTHxHEIGHT+X+Y`, where X and Y can also start with `-`.
+    fn from_str(geometry: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid geometry '{geometry}', expected WIDTHxHEIGHT or WIDTHxHEIGHT+X+Y");
+        let (width, rest) = geometry.split_once('x').ok_or_else(invalid)?;
+        let size_end = rest.find(['+', '-']).unwrap_or(rest.len());
+        let (height, position) = rest.split_at(size_end);
+        let width = width.parse().map_err(|_| invalid())?;
+        let height = height.parse().map_err(|_| invalid())?;
+        if width == 0 || height == 0 {
+            return Err(invalid())
+        }
+
+        let position = if position.is_empty() {
+            None
+        } else {
+            let y_start = position[1..].find(['+', '-']).map(|index| index + 1).ok_or_else(invalid)?;
+            let (x, y) = position.split_at(y_start);
+            let x = x.trim_start_matches('+').parse().map_err(|_| invalid())?;
+            let y = y.trim_start_matches('+').parse().map_err(|_| invalid())?;
+            Some((x, y))
+        };
+
+        Ok(Geometry { width, height, position })
+    }
+}
+
+impl Args {
+    /// Parses the arguments xtea was started with.
+    pub fn parse() -> Result<Args, String> {
+        Args::parse_from(std::env::args_os().skip(1))
+    }
+
+    /// Parses `args`, without the program name.
+    pub fn parse_from(args: impl IntoIterator<Item = OsString>) -> Result<Args, String> {
+        let mut parsed = Args::default();
+        let mut args = args.into_iter();
+        let mut only_files = false;
+        while let Some(arg) = args.next() {
+            let Some(arg_str) = arg.to_str().filter(|arg| !only_files && arg.starts_with('-') && *arg != "-") else {
+                parsed.files.push(PathBuf::from(arg));
+                continue
+            };
+            // Both `--option value` and `--option=value`
+            let (option, inline_value) = match arg_str.split_once('=') {
+                Some((option, value)) if option.starts_with("--") => (option, Some(OsString::from(value))),
+                _ => (arg_str, None),
+            };
+            let mut value = || inline_value.clone()
+                .or_else(|| args.next())
+                .ok_or_else(|| format!("{option} requires a value"));
+            let flag = || match inline_value {
+                Some(_) => Err(format!("{option} doesn't take a value")),
+                None => Ok(true),
+            };
+
+            match option {
+                "--" => only_files = flag()?,
+                "--plugin-dir" => parsed.plugin_dirs.push(PathBuf::from(value()?)),
+                "--only" => parsed.load_filter.only.get_or_insert_with(Vec::new).extend(plugin_list(option, value()?)?),
+                "--disable" => parsed.load_filter.disable.extend(plugin_list(option, value()?)?),
+                "--safe-mode" => parsed.load_filter.safe_mode = flag()?,
+                "--list-plugins" => parsed.list_plugins = flag()?,
+                "--config" => parsed.config = Some(PathBuf::from(value()?)),
+                "--log-stderr" => parsed.log_stderr = flag()?,
+                "--log" => parsed.log_filter = Some(string(option, value()?)?.parse()?),
+                "--log-level" => {
+                    let level = string(option, value()?)?;
+                    parsed.log_level = Some(level.parse()
+                        .map_err(|_| format!("invalid log level '{level}', expected off, error, warn, info, debug or trace"))?)
+                },
+                "--geometry" => parsed.geometry = Some(string(option, value()?)?.parse()?),
+                "--screenshot" => parsed.screenshot = Some(PathBuf::from(value()?)),
+                "--frames" => {
+                    let frames = string(option, value()?)?;
+                    parsed.frames = frames.parse().ok().filter(|&frames| frames > 0)
+                        .ok_or_else(|| format!("invalid number of frames '{frames}', expected at least 1"))?
+                },
+                "-h" | "--help" => parsed.help = flag()?,
+                "-V" | "--version" => parsed.version = flag()?,
+                _ => return Err(format!("unknown option {option}")),
+            }
+        }
+
+        Ok(parsed)
+    }
+}
+
+fn string(option: &str, value: OsString) -> Result<String, String> {
+    value.into_string().map_err(|value| format!("invalid value for {option}: {}", value.to_string_lossy()))
+}
+
+/// Splits a comma separated list of plugins.
+fn plugin_list(option: &str, value: OsString) -> Result<Vec<String>, String> {
+    Ok(string(option, value)?.split(',').map(str::trim).filter(|plugin| !plugin.is_empty()).map(str::to_string).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Args, String> {
+        Args::parse_from(args.iter().map(OsString::from))
+    }
+
+    fn geometry(width: u32, height: u32, position: Option<(i32, i32)>) -> Result<Geometry, String> {
+        Ok(Geometry { width, height, position })
+    }
+
+    #[test]
+    fn options_take_separate_or_inline_values() {
+        let args = parse(&["--plugin-dir", "a", "--plugin-dir=b", "--only", "One, Two,", "--disable=Three", "--frames=5"]).unwrap();
+        assert_eq!(args.plugin_dirs, [PathBuf::from("a"), PathBuf::from("b")]);
+        assert_eq!(args.load_filter.only, Some(vec!["One".to_string(), "Two".to_string()]));
+        assert_eq!(args.load_filter.disable, ["Three"]);
+        assert_eq!(args.frames, 5);
+    }
+
+    #[test]
+    fn values_can_contain_equal_signs() {
+        let args = parse(&["--log=info,my_plugin=debug", "--config", "a=b.toml"]).unwrap();
+        assert_eq!(args.log_filter.unwrap().to_string(), "info,my_plugin=debug");
+        assert_eq!(args.config, Some(PathBuf::from("a=b.toml")));
+    }
+
+    #[test]
+    fn flags_dont_take_values() {
+        let args = parse(&["--safe-mode", "--list-plugins", "--log-stderr", "-h", "-V"]).unwrap();
+        assert!(args.load_filter.safe_mode && args.list_plugins && args.log_stderr && args.help && args.version);
+
+        assert_eq!(parse(&["--safe-mode=false"]).unwrap_err(), "--safe-mode doesn't take a value");
+        assert_eq!(parse(&["--log-stderr=1"]).unwrap_err(), "--log-stderr doesn't take a value");
+        assert_eq!(parse(&["--help="]).unwrap_err(), "--help doesn't take a value");
+    }
+
+    #[test]
+    fn files_are_the_other_arguments() {
+        let args = parse(&["notes.txt", "-", "--safe-mode", "--", "--help", "-V"]).unwrap();
+        assert_eq!(args.files, [PathBuf::from("notes.txt"), PathBuf::from("-"), PathBuf::from("--help"), PathBuf::from("-V")]);
+        assert!(args.load_filter.safe_mode);
+        assert!(!args.help && !args.version);
+    }
+
+    #[test]
+    fn invalid_arguments_are_errors() {
+        assert_eq!(parse(&["--plugin-dir"]).unwrap_err(), "--plugin-dir requires a value");
+        assert_eq!(parse(&["--unknown"]).unwrap_err(), "unknown option --unknown");
+        assert_eq!(parse(&["--frames", "0"]).unwrap_err(), "invalid number of frames '0', expected at least 1");
+        assert!(parse(&["--log-level", "loud"]).unwrap_err().starts_with("invalid log level 'loud'"));
+        assert!(parse(&["--log", "a=loud"]).is_err());
+    }
+
+    #[test]
+    fn geometry_is_a_size_and_an_optional_position() {
+        assert_eq!("800x600".parse(), geometry(800, 600, None));
+        assert_eq!("800x600+10+20".parse(), geometry(800, 600, Some((10, 20))));
+        assert_eq!("800x600-10+20".parse(), geometry(800, 600, Some((-10, 20))));
+        assert_eq!("800x600+10-20".parse(), geometry(800, 600, Some((10, -20))));
+    }
+
+    #[test]
+    fn invalid_geometries_are_errors() {
+        for invalid in ["", "800", "800x", "x600", "0x600", "800x0", "800x600+10", "800x600+", "axb", "800x600+a+b", "-800x600"] {
+            assert_eq!(
+                invalid.parse::<Geometry>(),
+                Err(format!("invalid geometry '{invalid}', expected WIDTHxHEIGHT or WIDTHxHEIGHT+X+Y")),
+                "{invalid}",
+            )
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+
+use xtealib::{LogFilter, LogFilters};
+
+use crate::{logs::LogFormat, plugins::PluginId};
+
+const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Settings that persist between runs, stored as toml in the user config directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub plugins: PluginsConfig,
+    pub logs: LogsConfig,
+    /// Where the config is saved. `None` if it must not be overwritten.
+    #[serde(skip)]
+    path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PluginsConfig {
+    pub ui_build_order: Vec<PluginId>,
+    pub view_submenu_order: Vec<PluginId>,
+    pub restart: RestartPolicy,
+    /// A plugin that takes longer to initialize is reported in the logs.
+    pub init_timeout_secs: u64,
+    /// How long xtea waits for an unloaded or reloaded plugin to stop its tasks, the UI is frozen meanwhile.
+    pub shutdown_timeout_ms: u64,
+    /// Plugins that were ever loaded.
+    pub known: BTreeMap<PluginId, PluginConfig>,
+}
+
+impl Default for PluginsConfig {
+    fn default() -> Self {
+        PluginsConfig {
+            ui_build_order: Vec::new(),
+            view_submenu_order: Vec::new(),
+            restart: RestartPolicy::default(),
+            init_timeout_secs: 10,
+            shutdown_timeout_ms: 2000,
+            known: BTreeMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PluginConfig {
+    /// To show the plugin without loading its library while it's disabled.
+    #[serde(default)]
+    pub name: String,
+    pub enabled: bool,
+    pub library: PathBuf,
+    /// Whether the plugin is reloaded when its library changes.
+    #[serde(default = "default_auto_reload")]
+    pub auto_reload: bool,
+}
+
+fn default_auto_reload() -> bool {
+    true
+}
+
+/// What to do with a plugin that panicked.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RestartPolicy {
+    /// Restart it right away, instead of waiting for "Restart" in the Plugin Manager.
+    pub auto_restart: bool,
+    /// How many times a plugin is restarted automatically in a single run.
+    pub max_restarts: u32,
+}
+
+impl Default for RestartPolicy {
+    fn default() -> Self {
+        RestartPolicy { auto_restart: false, max_restarts: 3 }
+    }
+}
+
+/// Which logs are kept, and how many.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogsConfig {
+    /// Messages kept per target, the oldest ones are dropped past that.
+    pub capacity_per_target: usize,
+    /// Messages kept in the "All" tab, the oldest ones are dropped past that.
+    pub capacity_all: usize,
+    /// Like `RUST_LOG`, see [`LogFilter`].
+    pub filter: String,
+    /// Filters replacing `filter` for some plugins, by plugin name.
+    pub plugins: BTreeMap<String, String>,
+    /// Files the logs are written to as they arrive.
+    pub sinks: Vec<LogSinkConfig>,
+}
+
+/// A log file, from `[[logs.sinks]]`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LogSinkConfig {
+    /// Relative to the directory xtea is started in. `{plugin}` is replaced with the name of
+    /// the plugin that logged each record, or `xtea`, for a file per plugin.
+    pub path: PathBuf,
+    #[serde(default)]
+    pub format: LogFormat,
+    /// Only the records of this plugin, or of xtea itself with `xtea`.
+    #[serde(default)]
+    pub plugin: Option<String>,
+    /// The file is rotated before it gets bigger than this.
+    #[serde(default)]
+    pub max_size_bytes: Option<u64>,
+    /// The file is rotated once it was created longer ago than this.
+    #[serde(default)]
+    pub max_age_secs: Option<u64>,
+    /// How many rotated files are kept, from `<name>.1.<ext>`, the newest, to `<name>.<keep>.<ext>`.
+    #[serde(default = "default_keep")]
+    pub keep: usize,
+    /// Rotated files last written longer ago than this are deleted, even when there are fewer than `keep`.
+    #[serde(default)]
+    pub keep_for_secs: Option<u64>,
+}
+
+fn default_keep() -> usize {
+    5
+}
+
+impl Default for LogsConfig {
+    fn default() -> Self {
+        LogsConfig {
+            capacity_per_target: 10_000,
+            capacity_all: 100_000,
+            filter: LogFilter::default().to_string(),
+            plugins: BTreeMap::new(),
+            sinks: Vec::new(),
+        }
+    }
+}
+
+impl LogsConfig {
+    /// The filters, without the ones that can't be parsed.
+    pub fn filters(&self) -> LogFilters {
+        let parse = |what: &str, filter: &str| filter.parse::<LogFilter>()
+            .map_err(|e| error!("Invalid {what} in the config\n\t{e}"))
+            .ok();
+        LogFilters {
+            global: parse("log filter", &self.filter).unwrap_or_default(),
+            plugins: self.plugins.iter()
+                .filter_map(|(plugin, filter)| Some((plugin.clone(), parse(&format!("log filter of {plugin}"), filter)?)))
+                .collect(),
+        }
+    }
+}
+
+impl Config {
+    pub fn default_path() -> Option<PathBuf> {
+        dirs::config_dir().map(|config_dir| config_dir.join("xtea").join(CONFIG_FILE_NAME))
+    }
+
+    /// Loads the config at `path`, or the default config if it doesn't exist yet.
+    pub fn load(path: Option<PathBuf>) -> Config {
+        let Some(path) = path else {
+            return Config::default()
+        };
+        if !path.exists() {
+            info!("No config at {}, using the default config", path.display());
+            return Config { path: Some(path), ..Default::default() }
+        }
+
+        let config = std::fs::read_to_string(&path)
+            .map_err(|e| e.to_string())
+            .and_then(|content| toml::from_str::<Config>(&content).map_err(|e| e.to_string()));
+        match config {
+            Ok(mut config) => {
+                config.path = Some(path);
+                config
+            },
+            Err(e) => {
+                error!("Could not load config {}, it won't be overwritten\n\t{e}", path.display());
+                Config::default()
+            },
+        }
+    }
+
+    pub fn save(&self) {
+        let Some(path) = &self.path else {
+            return
+        };
+        let content = match toml::to_string_pretty(self) {
+            Ok(content) => content,
+            Err(e) => {
+                error!("Could not serialize config\n\t{e}");
+                return
+            },
+        };
+        if let Some(parent) = path.parent() {
+            if let Err(e) = std::fs::create_dir_all(parent) {
+                error!("Could not create config directory {}\n\t{e}", parent.display());
+                return
+            }
+        }
+        if let Err(e) = std::fs::write(path, content) {
+            error!("Could not save config {}\n\t{e}", path.display())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-config-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn plugin_config(enabled: bool, library: &str) -> PluginConfig {
+        PluginConfig { name: "Counter".to_string(), enabled, library: PathBuf::from(library), auto_reload: false }
+    }
+
+    #[test]
+    fn saved_configs_are_loaded_back() {
+        let path = test_dir("round-trip").join("xtea").join(CONFIG_FILE_NAME);
+        let mut config = Config::load(Some(path.clone()));
+        let counter: PluginId = toml::Value::String("com.example.counter".to_string()).try_into().unwrap();
+        config.plugins.ui_build_order = vec![counter.clone()];
+        config.plugins.view_submenu_order = vec![counter.clone()];
+        config.plugins.known.insert(counter.clone(), plugin_config(false, "plugins/libcounter.so"));
+        config.plugins.restart.auto_restart = true;
+        config.plugins.shutdown_timeout_ms = 500;
+        config.logs.filter = "info,counter=trace".to_string();
+        config.logs.plugins.insert("Counter".to_string(), "debug".to_string());
+        config.logs.sinks.push(LogSinkConfig {
+            path: PathBuf::from("logs/{plugin}.csv"),
+            format: LogFormat::Csv,
+            plugin: None,
+            max_size_bytes: Some(1000),
+            max_age_secs: None,
+            keep: 2,
+            keep_for_secs: Some(60),
+        });
+        config.save();
+
+        let loaded = Config::load(Some(path));
+        assert_eq!(toml::to_string(&loaded).unwrap(), toml::to_string(&config).unwrap());
+        assert_eq!(loaded.plugins.ui_build_order, std::slice::from_ref(&counter));
+        assert!(!loaded.plugins.known[&counter].enabled);
+        assert_eq!(loaded.logs.filters().plugins["Counter"].to_string(), "debug");
+    }
+
+    #[test]
+    fn missing_fields_get_their_default() {
+        let config: Config = toml::from_str(r#"
+            [plugins.known."Counter@plugins/libcounter.so"]
+            enabled = true
+            library = "plugins/libcounter.so"
+        "#).unwrap();
+        let (_, known) = config.plugins.known.iter().next().unwrap();
+        assert!(known.enabled);
+        assert!(known.auto_reload);
+        assert_eq!(known.name, "");
+        assert_eq!(config.plugins.shutdown_timeout_ms, 2000);
+        assert_eq!(config.logs.capacity_per_target, 10_000);
+        assert_eq!(config.logs.filters(), LogFilters::default());
+    }
+
+    #[test]
+    fn a_missing_config_is_created_and_an_invalid_one_is_kept() {
+        let dir = test_dir("missing");
+        let config = Config::load(Some(dir.join(CONFIG_FILE_NAME)));
+        assert!(config.plugins.known.is_empty());
+        config.save();
+        assert!(dir.join(CONFIG_FILE_NAME).is_file());
+
+        std::fs::write(dir.join(CONFIG_FILE_NAME), "[plugins\n").unwrap();
+        let config = Config::load(Some(dir.join(CONFIG_FILE_NAME)));
+        assert_eq!(config.path, None);
+        config.save();
+        assert_eq!(std::fs::read_to_string(dir.join(CONFIG_FILE_NAME)).unwrap(), "[plugins\n");
+    }
+}
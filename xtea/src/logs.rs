@@ -0,0 +1,269 @@
+//! The logs xtea keeps: a bounded history per target for the Logs window, and the log files.
+
+use std::{collections::{BTreeMap, VecDeque}, ops::Range, sync::Arc};
+
+use xtealib::{ImguiLogger, LogRecord};
+
+pub use self::{format::{export, LogFormat}, sinks::LogSinks};
+
+mod format;
+mod sinks;
+
+/// How many records are taken from the logger in a frame, so a burst of messages is spread over several frames.
+const MAX_DRAINED_PER_FRAME: usize = 10_000;
+
+/// The records of every target, keeping the newest `capacity` of each,
+/// and the newest `all_capacity` of all of them together.
+pub struct LogStore {
+    targets: BTreeMap<String, TargetLog>,
+    /// Every target, in the order the records arrived.
+    all: TargetLog,
+    capacity: usize,
+    all_capacity: usize,
+    /// Where every record is also written.
+    sinks: LogSinks,
+}
+
+/// Records, oldest first. Each record has an index that doesn't change while it's kept,
+/// from [`range`](TargetLog::range).
+#[derive(Default)]
+pub struct TargetLog {
+    // Shared with `LogStore::all`
+    records: VecDeque<Arc<LogRecord>>,
+    /// The index of the oldest record.
+    first: u64,
+    /// Records removed to make room for newer ones.
+    evicted: u64,
+    /// Changes when records other than the oldest are removed, and the indexes are given to other records.
+    generation: u64,
+    /// The plugin that logged to this target last, kept when its records are removed.
+    plugin: Option<Arc<str>>,
+}
+
+impl LogStore {
+    pub fn new(capacity: usize, all_capacity: usize, sinks: LogSinks) -> LogStore {
+        LogStore {
+            targets: BTreeMap::new(),
+            all: TargetLog::default(),
+            capacity: capacity.max(1),
+            all_capacity: all_capacity.max(1),
+            sinks,
+        }
+    }
+
+    /// Takes the records logged since the last call, up to a limit per call.
+    pub fn drain(&mut self, logger: &ImguiLogger) {
+        logger.drain(MAX_DRAINED_PER_FRAME, |record| self.push(record));
+    }
+
+    /// Takes every record left in `logger`, and finishes writing them to the sinks.
+    pub fn flush(&mut self, logger: &ImguiLogger) {
+        while logger.drain(MAX_DRAINED_PER_FRAME, |record| self.push(record)) > 0 {}
+        self.sinks.close()
+    }
+
+    pub fn push(&mut self, record: LogRecord) {
+        // Only allocates the key for a new target
+        if !self.targets.contains_key(&record.target) {
+            self.targets.insert(record.target.clone(), TargetLog::default());
+        }
+        let log = self.targets.get_mut(&record.target).expect("the target was just added");
+        if record.plugin.is_some() && log.plugin != record.plugin {
+            log.plugin = record.plugin.clone()
+        }
+        let record = Arc::new(record);
+        self.sinks.write(&record);
+        log.push(record.clone(), self.capacity);
+        self.all.push(record, self.all_capacity)
+    }
+
+    /// The targets that logged something, by name.
+    pub fn targets(&self) -> impl Iterator<Item = (&str, &TargetLog)> {
+        self.targets.iter().map(|(target, log)| (target.as_str(), log))
+    }
+
+    pub fn target(&self, target: &str) -> Option<&TargetLog> {
+        self.targets.get(target)
+    }
+
+    pub fn sinks(&self) -> &LogSinks {
+        &self.sinks
+    }
+
+    /// The records of every target, in the order they arrived.
+    pub fn all(&self) -> &TargetLog {
+        &self.all
+    }
+
+    /// Removes the records of `target`, or of every target.
+    pub fn clear(&mut self, target: Option<&str>) {
+        match target {
+            Some(target) => {
+                if let Some(log) = self.targets.get_mut(target) {
+                    log.clear()
+                }
+                self.all.records.retain(|record| record.target != target);
+                self.all.generation += 1;
+            },
+            None => {
+                self.targets.values_mut().for_each(TargetLog::clear);
+                self.all.clear()
+            },
+        }
+    }
+}
+
+impl TargetLog {
+    fn push(&mut self, record: Arc<LogRecord>, capacity: usize) {
+        if self.records.len() >= capacity {
+            self.records.pop_front();
+            self.first += 1;
+            self.evicted += 1;
+        }
+        self.records.push_back(record)
+    }
+
+    fn clear(&mut self) {
+        self.first += self.records.len() as u64;
+        self.records.clear()
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &LogRecord> {
+        self.records.iter().map(Arc::as_ref)
+    }
+
+    /// The indexes of the records kept.
+    pub fn range(&self) -> Range<u64> {
+        self.first..self.first + self.records.len() as u64
+    }
+
+    pub fn get(&self, index: u64) -> Option<&LogRecord> {
+        let offset = usize::try_from(index.checked_sub(self.first)?).ok()?;
+        self.records.get(offset).map(Arc::as_ref)
+    }
+
+    pub fn evicted(&self) -> u64 {
+        self.evicted
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// The plugin that logs to this target, `None` for xtea's own targets.
+    pub fn plugin(&self) -> Option<&str> {
+        self.plugin.as_deref()
+    }
+}
+
+/// `name` with only the characters every file system accepts in a file name.
+pub fn file_name(name: &str) -> String {
+    name.chars()
+        .map(|c| if c.is_alphanumeric() || " -_.()".contains(c) { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use log::Level;
+
+    use super::*;
+
+    fn record(target: &str, plugin: Option<&str>, message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::Info,
+            timestamp: SystemTime::now(),
+            target: target.to_string(),
+            plugin: plugin.map(Arc::from),
+            module_path: None,
+            file: None,
+            line: None,
+            message: message.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    fn messages(log: &TargetLog) -> Vec<(u64, &str)> {
+        log.range().map(|index| (index, log.get(index).unwrap().message.as_str())).collect()
+    }
+
+    fn store(capacity: usize, all_capacity: usize) -> LogStore {
+        LogStore::new(capacity, all_capacity, LogSinks::new(Vec::new()))
+    }
+
+    #[test]
+    fn evicted_records_keep_the_indexes_of_the_others() {
+        let mut logs = store(3, 10);
+        for i in 0..5 {
+            logs.push(record("a", None, &i.to_string()))
+        }
+
+        let log = logs.target("a").unwrap();
+        assert_eq!(messages(log), [(2, "2"), (3, "3"), (4, "4")]);
+        assert_eq!(log.evicted(), 2);
+        assert_eq!(log.generation(), 0);
+        assert_eq!(log.get(1), None);
+    }
+
+    #[test]
+    fn all_has_its_own_capacity() {
+        let mut logs = store(2, 5);
+        for i in 0..4 {
+            logs.push(record("a", None, &format!("a{i}")));
+            logs.push(record("b", None, &format!("b{i}")));
+        }
+
+        assert_eq!(messages(logs.target("a").unwrap()), [(2, "a2"), (3, "a3")]);
+        assert_eq!(messages(logs.all()), [(3, "b1"), (4, "a2"), (5, "b2"), (6, "a3"), (7, "b3")]);
+        assert_eq!(logs.all().evicted(), 3);
+    }
+
+    #[test]
+    fn cleared_records_give_their_indexes_to_no_other_record() {
+        let mut logs = store(10, 10);
+        logs.push(record("a", None, "a0"));
+        logs.push(record("a", None, "a1"));
+        logs.clear(None);
+        logs.push(record("a", None, "a2"));
+
+        assert_eq!(messages(logs.target("a").unwrap()), [(2, "a2")]);
+        assert_eq!(messages(logs.all()), [(2, "a2")]);
+        assert_eq!(logs.target("a").unwrap().evicted(), 0);
+        assert_eq!(logs.all().generation(), 0);
+    }
+
+    #[test]
+    fn clearing_a_target_changes_the_generation_of_all() {
+        let mut logs = store(10, 10);
+        logs.push(record("a", None, "a0"));
+        logs.push(record("b", None, "b0"));
+        logs.push(record("a", None, "a1"));
+        logs.clear(Some("a"));
+
+        let a = logs.target("a").unwrap();
+        assert_eq!(a.range(), 2..2);
+        assert_eq!(a.generation(), 0);
+        assert_eq!(messages(logs.target("b").unwrap()), [(0, "b0")]);
+        // The records of the other targets move to lower indexes
+        assert_eq!(messages(logs.all()), [(0, "b0")]);
+        assert_eq!(logs.all().generation(), 1);
+
+        logs.push(record("a", None, "a2"));
+        assert_eq!(messages(logs.target("a").unwrap()), [(2, "a2")]);
+        assert_eq!(messages(logs.all()), [(0, "b0"), (1, "a2")]);
+    }
+
+    #[test]
+    fn targets_remember_their_plugin() {
+        let mut logs = store(1, 1);
+        logs.push(record("xtea::plugins", None, "loaded"));
+        logs.push(record("counter", Some("Counter"), "counted"));
+        logs.push(record("counter", None, "from an older xtealib"));
+        logs.clear(None);
+
+        assert_eq!(logs.target("xtea::plugins").unwrap().plugin(), None);
+        assert_eq!(logs.target("counter").unwrap().plugin(), Some("Counter"));
+    }
+}
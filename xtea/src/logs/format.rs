@@ -0,0 +1,178 @@
+//! The formats logs are written in, to log files and exports.
+
+use std::{fs::File, io::{self, BufWriter, Write}, path::Path};
+
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use xtealib::LogRecord;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    /// `timestamp [target] LEVEL - message key=value...`
+    #[default]
+    Text,
+    /// A JSON object per record.
+    Jsonl,
+    /// A row per record, after a header.
+    Csv,
+}
+
+const CSV_HEADER: &str = "timestamp,level,target,plugin,module_path,file,line,message,fields\n";
+
+impl LogFormat {
+    pub const ALL: [LogFormat; 3] = [LogFormat::Text, LogFormat::Jsonl, LogFormat::Csv];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            LogFormat::Text => "Text",
+            LogFormat::Jsonl => "JSON Lines",
+            LogFormat::Csv => "CSV",
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            LogFormat::Text => "txt",
+            LogFormat::Jsonl => "jsonl",
+            LogFormat::Csv => "csv",
+        }
+    }
+
+    /// What a file starts with, before the records.
+    pub fn header(self) -> &'static str {
+        match self {
+            LogFormat::Csv => CSV_HEADER,
+            LogFormat::Text | LogFormat::Jsonl => "",
+        }
+    }
+
+    /// Writes `record` and the line break after it.
+    pub fn write(self, out: &mut impl Write, record: &LogRecord) -> io::Result<()> {
+        let timestamp = humantime::format_rfc3339_millis(record.timestamp);
+        match self {
+            LogFormat::Text => writeln!(out, "{timestamp} [{}] {record}", record.target),
+            LogFormat::Jsonl => {
+                let value = json!({
+                    "timestamp": timestamp.to_string(),
+                    "level": record.level.as_str(),
+                    "target": record.target,
+                    "plugin": record.plugin.as_deref(),
+                    "module_path": record.module_path,
+                    "file": record.file,
+                    "line": record.line,
+                    "message": record.message,
+                    "fields": fields(record),
+                });
+                serde_json::to_writer(&mut *out, &value)?;
+                writeln!(out)
+            },
+            LogFormat::Csv => {
+                let timestamp = timestamp.to_string();
+                let line = record.line.map(|line| line.to_string());
+                // The key-values as a JSON object, their keys are free-form
+                let fields = Value::Object(fields(record)).to_string();
+                let columns: [&str; 9] = [
+                    &timestamp,
+                    record.level.as_str(),
+                    &record.target,
+                    record.plugin.as_deref().unwrap_or_default(),
+                    record.module_path.as_deref().unwrap_or_default(),
+                    record.file.as_deref().unwrap_or_default(),
+                    line.as_deref().unwrap_or_default(),
+                    &record.message,
+                    &fields,
+                ];
+                let columns = columns.map(csv_field);
+                writeln!(out, "{}", columns.join(","))
+            },
+        }
+    }
+}
+
+fn fields(record: &LogRecord) -> Map<String, Value> {
+    record.fields.iter().map(|(key, value)| (key.clone(), Value::String(value.clone()))).collect()
+}
+
+/// Quotes `field` if it has a comma, a quote or a line break.
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes `records` to a new file at `path`, creating its directory.
+pub fn export<'a>(path: &Path, format: LogFormat, records: impl Iterator<Item = &'a LogRecord>) -> io::Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        std::fs::create_dir_all(dir)?
+    }
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(format.header().as_bytes())?;
+    for record in records {
+        format.write(&mut out, record)?
+    }
+
+    out.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use log::Level;
+
+    use super::*;
+
+    fn record(message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::Warn,
+            timestamp: SystemTime::UNIX_EPOCH + Duration::from_millis(1_500),
+            target: "my_plugin::io".to_string(),
+            plugin: Some("My Plugin".into()),
+            module_path: None,
+            file: Some("src/io.rs".to_string()),
+            line: Some(12),
+            message: message.to_string(),
+            fields: vec![("path".to_string(), "a,b.txt".to_string())],
+        }
+    }
+
+    fn write(format: LogFormat, record: &LogRecord) -> String {
+        let mut out = Vec::new();
+        format.write(&mut out, record).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn csv_fields_are_quoted_when_needed() {
+        assert_eq!(csv_field("plain text"), "plain text");
+        assert_eq!(csv_field(""), "");
+        assert_eq!(csv_field("a,b"), "\"a,b\"");
+        assert_eq!(csv_field("say \"hi\""), "\"say \"\"hi\"\"\"");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+        assert_eq!(csv_field("carriage\rreturn"), "\"carriage\rreturn\"");
+    }
+
+    #[test]
+    fn csv_lines_have_every_column() {
+        assert_eq!(CSV_HEADER.lines().next().unwrap().split(',').count(), 9);
+        assert_eq!(
+            write(LogFormat::Csv, &record("Could not open \"a,b.txt\"")),
+            "1970-01-01T00:00:01.500Z,WARN,my_plugin::io,My Plugin,,src/io.rs,12,\
+             \"Could not open \"\"a,b.txt\"\"\",\"{\"\"path\"\":\"\"a,b.txt\"\"}\"\n",
+        );
+    }
+
+    #[test]
+    fn jsonl_records_are_one_line() {
+        let line = write(LogFormat::Jsonl, &record("two\nlines"));
+        assert_eq!(line.lines().count(), 1);
+        let value: Value = serde_json::from_str(&line).unwrap();
+        assert_eq!(value["message"], "two\nlines");
+        assert_eq!(value["plugin"], "My Plugin");
+        assert_eq!(value["module_path"], Value::Null);
+        assert_eq!(value["fields"]["path"], "a,b.txt");
+    }
+}
@@ -0,0 +1,409 @@
+//! Log files written as the records arrive, rotated when they get too big or too old.
+
+use std::{
+    collections::{hash_map::Entry, HashMap, HashSet},
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicU64, Ordering}, mpsc::{self, Receiver, SyncSender, TrySendError}, Arc},
+    thread::JoinHandle,
+    time::{Duration, SystemTime},
+};
+
+use log::error;
+use xtealib::LogRecord;
+
+use crate::config::LogSinkConfig;
+
+use super::{file_name, LogFormat};
+
+/// The plugin name of xtea's own records, in sink paths and filters.
+const XTEA_SOURCE: &str = "xtea";
+
+/// How many records can wait to be written, newer ones are dropped.
+const PENDING_CAPACITY: usize = 65_536;
+
+/// Writes the records to the sinks of the config, in a thread of their own.
+///
+/// The records wait in a bounded channel, so a slow disk drops the newest records
+/// instead of keeping every record in memory.
+pub struct LogSinks {
+    sender: Option<SyncSender<Arc<LogRecord>>>,
+    thread: Option<JoinHandle<()>>,
+    /// Records that didn't fit in the channel.
+    dropped: AtomicU64,
+}
+
+impl LogSinks {
+    pub fn new(configs: Vec<LogSinkConfig>) -> LogSinks {
+        let none = || LogSinks { sender: None, thread: None, dropped: AtomicU64::new(0) };
+        if configs.is_empty() {
+            return none()
+        }
+        let (sender, receiver) = mpsc::sync_channel(PENDING_CAPACITY);
+        let thread = std::thread::Builder::new()
+            .name("log sinks".to_string())
+            .spawn(move || write_sinks(configs, receiver));
+        match thread {
+            Ok(thread) => LogSinks { sender: Some(sender), thread: Some(thread), dropped: AtomicU64::new(0) },
+            Err(e) => {
+                error!("Could not start writing the log files\n\t{e}");
+                none()
+            },
+        }
+    }
+
+    pub fn write(&self, record: &Arc<LogRecord>) {
+        if let Some(sender) = &self.sender {
+            // Disconnected only if the thread panicked
+            if let Err(TrySendError::Full(_)) = sender.try_send(record.clone()) {
+                self.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// How many records weren't written because they came faster than the files could be written.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Writes the records sent so far, and closes the files.
+    pub fn close(&mut self) {
+        self.sender = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for LogSinks {
+    fn drop(&mut self) {
+        self.close()
+    }
+}
+
+fn write_sinks(configs: Vec<LogSinkConfig>, records: Receiver<Arc<LogRecord>>) {
+    let mut sinks = configs.into_iter().map(Sink::new).collect::<Vec<_>>();
+    while let Ok(record) = records.recv() {
+        for record in std::iter::once(record).chain(records.try_iter()) {
+            sinks.iter_mut().for_each(|sink| sink.write(&record))
+        }
+        // Once the burst is written
+        sinks.iter_mut().for_each(Sink::flush)
+    }
+}
+
+struct Sink {
+    config: LogSinkConfig,
+    /// By path, there's one per plugin when the path has `{plugin}`.
+    files: HashMap<PathBuf, SinkFile>,
+    /// Files that couldn't be written, they aren't tried again.
+    failed: HashSet<PathBuf>,
+}
+
+struct SinkFile {
+    writer: BufWriter<File>,
+    size: u64,
+    /// When the file was created.
+    started: SystemTime,
+}
+
+impl Sink {
+    fn new(config: LogSinkConfig) -> Sink {
+        Sink { config, files: HashMap::new(), failed: HashSet::new() }
+    }
+
+    fn write(&mut self, record: &LogRecord) {
+        let source = record.plugin.as_deref().unwrap_or(XTEA_SOURCE);
+        if self.config.plugin.as_deref().is_some_and(|plugin| plugin != source) {
+            return
+        }
+        let path = self.path(source);
+        if self.failed.contains(&path) {
+            return
+        }
+
+        let mut line = Vec::new();
+        // Writing to a `Vec` doesn't fail
+        let _ = self.config.format.write(&mut line, record);
+        if let Err(e) = self.write_line(&path, &line) {
+            error!("Could not write the logs to {}, they won't be written there anymore\n\t{e}", path.display());
+            self.files.remove(&path);
+            self.failed.insert(path);
+        }
+    }
+
+    /// The file of the records of `source`.
+    fn path(&self, source: &str) -> PathBuf {
+        let path = self.config.path.to_string_lossy();
+        if path.contains("{plugin}") {
+            PathBuf::from(path.replace("{plugin}", &file_name(source)))
+        } else {
+            self.config.path.clone()
+        }
+    }
+
+    fn write_line(&mut self, path: &Path, line: &[u8]) -> io::Result<()> {
+        let format = self.config.format;
+        let file = match self.files.entry(path.to_path_buf()) {
+            Entry::Occupied(entry) => entry.into_mut(),
+            Entry::Vacant(entry) => {
+                prune(path, self.config.keep, self.config.keep_for_secs)?;
+                entry.insert(SinkFile::open(path, format)?)
+            },
+        };
+        if self.config.needs_rotation(file, line.len() as u64) {
+            file.writer.flush()?;
+            self.files.remove(path);
+            rotate(path, self.config.keep)?;
+            prune(path, self.config.keep, self.config.keep_for_secs)?;
+            self.files.insert(path.to_path_buf(), SinkFile::open(path, format)?);
+        }
+
+        let file = self.files.get_mut(path).expect("the file was just opened");
+        file.writer.write_all(line)?;
+        file.size += line.len() as u64;
+        Ok(())
+    }
+
+    fn flush(&mut self) {
+        let mut failed = Vec::new();
+        for (path, file) in &mut self.files {
+            if let Err(e) = file.writer.flush() {
+                error!("Could not write the logs to {}, they won't be written there anymore\n\t{e}", path.display());
+                failed.push(path.clone())
+            }
+        }
+        for path in failed {
+            self.files.remove(&path);
+            self.failed.insert(path);
+        }
+    }
+}
+
+impl LogSinkConfig {
+    /// Whether `file` must be rotated before writing `len` more bytes. A file with no records never is.
+    fn needs_rotation(&self, file: &SinkFile, len: u64) -> bool {
+        if file.size <= self.format.header().len() as u64 {
+            return false
+        }
+        let too_big = self.max_size_bytes.is_some_and(|max_size| file.size + len > max_size);
+        let too_old = self.max_age_secs.is_some_and(|max_age| {
+            file.started.elapsed().unwrap_or_default() >= Duration::from_secs(max_age)
+        });
+
+        too_big || too_old
+    }
+}
+
+impl SinkFile {
+    /// Appends to the file at `path`, or creates it with its directory.
+    fn open(path: &Path, format: LogFormat) -> io::Result<SinkFile> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)?
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let metadata = file.metadata()?;
+        let mut sink_file = SinkFile {
+            writer: BufWriter::new(file),
+            size: metadata.len(),
+            // Not every file system knows when a file was created
+            started: metadata.created().or_else(|_| metadata.modified()).unwrap_or_else(|_| SystemTime::now()),
+        };
+        if sink_file.size == 0 {
+            sink_file.started = SystemTime::now();
+            sink_file.writer.write_all(format.header().as_bytes())?;
+            sink_file.size = format.header().len() as u64;
+        }
+
+        Ok(sink_file)
+    }
+}
+
+/// `name.ext` becomes `name.1.ext`, `name.1.ext` becomes `name.2.ext`, and so on up to `name.<keep>.ext`.
+fn rotate(path: &Path, keep: usize) -> io::Result<()> {
+    let ignore_missing = |result: io::Result<()>| match result {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => Err(e),
+        _ => Ok(()),
+    };
+    if keep == 0 {
+        return std::fs::remove_file(path)
+    }
+
+    ignore_missing(std::fs::remove_file(numbered(path, keep)))?;
+    for number in (1..keep).rev() {
+        ignore_missing(std::fs::rename(numbered(path, number), numbered(path, number + 1)))?
+    }
+    std::fs::rename(path, numbered(path, 1))
+}
+
+/// Deletes the rotated files of `path` last written longer than `keep_for_secs` ago.
+fn prune(path: &Path, keep: usize, keep_for_secs: Option<u64>) -> io::Result<()> {
+    let Some(keep_for) = keep_for_secs.map(Duration::from_secs) else {
+        return Ok(())
+    };
+    for number in 1..=keep {
+        let rotated = numbered(path, number);
+        let age = match std::fs::metadata(&rotated).and_then(|metadata| metadata.modified()) {
+            Ok(modified) => modified.elapsed().unwrap_or_default(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => return Err(e),
+        };
+        if age > keep_for {
+            std::fs::remove_file(rotated)?
+        }
+    }
+
+    Ok(())
+}
+
+fn numbered(path: &Path, number: usize) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    match path.extension() {
+        Some(extension) => path.with_file_name(format!("{stem}.{number}.{}", extension.to_string_lossy())),
+        None => path.with_file_name(format!("{stem}.{number}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use log::Level;
+
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-sinks-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn config(path: PathBuf, format: LogFormat) -> LogSinkConfig {
+        LogSinkConfig { path, format, plugin: None, max_size_bytes: None, max_age_secs: None, keep: 5, keep_for_secs: None }
+    }
+
+    fn record(plugin: Option<&str>, message: &str) -> LogRecord {
+        LogRecord {
+            level: Level::Info,
+            timestamp: SystemTime::now(),
+            target: "target".to_string(),
+            plugin: plugin.map(Arc::from),
+            module_path: None,
+            file: None,
+            line: None,
+            message: message.to_string(),
+            fields: Vec::new(),
+        }
+    }
+
+    /// The messages in a jsonl file.
+    fn messages(path: &Path) -> Vec<String> {
+        std::fs::read_to_string(path).unwrap().lines()
+            .map(|line| serde_json::from_str::<serde_json::Value>(line).unwrap()["message"].as_str().unwrap().to_string())
+            .collect()
+    }
+
+    #[test]
+    fn rotated_files_are_numbered_newest_first() {
+        let dir = test_dir("rotate");
+        let path = dir.join("xtea.log");
+        for content in ["first", "second", "third"] {
+            std::fs::write(&path, content).unwrap();
+            rotate(&path, 2).unwrap();
+        }
+
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(dir.join("xtea.1.log")).unwrap(), "third");
+        assert_eq!(std::fs::read_to_string(dir.join("xtea.2.log")).unwrap(), "second");
+        assert!(!dir.join("xtea.3.log").exists());
+
+        std::fs::write(&path, "fourth").unwrap();
+        rotate(&path, 0).unwrap();
+        assert!(!path.exists());
+        assert_eq!(std::fs::read_to_string(dir.join("xtea.1.log")).unwrap(), "third");
+    }
+
+    #[test]
+    fn numbered_paths_keep_the_extension() {
+        assert_eq!(numbered(Path::new("logs/xtea.jsonl"), 3), Path::new("logs/xtea.3.jsonl"));
+        assert_eq!(numbered(Path::new("logs/xtea"), 1), Path::new("logs/xtea.1"));
+    }
+
+    #[test]
+    fn sinks_rotate_before_getting_too_big() {
+        let dir = test_dir("size");
+        let path = dir.join("xtea.jsonl");
+        let line_len = {
+            let mut line = Vec::new();
+            LogFormat::Jsonl.write(&mut line, &record(None, "0")).unwrap();
+            line.len() as u64
+        };
+        let mut sink = Sink::new(LogSinkConfig {
+            max_size_bytes: Some(2 * line_len + 10),
+            keep: 2,
+            ..config(path.clone(), LogFormat::Jsonl)
+        });
+        for i in 0..7 {
+            sink.write(&record(None, &i.to_string()))
+        }
+        sink.flush();
+
+        assert_eq!(messages(&path), ["6"]);
+        assert_eq!(messages(&dir.join("xtea.1.jsonl")), ["4", "5"]);
+        assert_eq!(messages(&dir.join("xtea.2.jsonl")), ["2", "3"]);
+        assert!(!dir.join("xtea.3.jsonl").exists());
+    }
+
+    #[test]
+    fn every_csv_file_has_the_header() {
+        let dir = test_dir("csv");
+        let path = dir.join("xtea.csv");
+        let mut sink = Sink::new(LogSinkConfig { max_size_bytes: Some(1), ..config(path.clone(), LogFormat::Csv) });
+        sink.write(&record(None, "first"));
+        sink.write(&record(None, "second"));
+        sink.flush();
+
+        for path in [path, dir.join("xtea.1.csv")] {
+            let content = std::fs::read_to_string(&path).unwrap();
+            assert!(content.starts_with(LogFormat::Csv.header()), "{content}");
+            assert_eq!(content.lines().count(), 2, "{content}");
+        }
+    }
+
+    #[test]
+    fn old_rotated_files_are_deleted() {
+        let dir = test_dir("age");
+        let path = dir.join("xtea.jsonl");
+        for (number, age) in [(1, 60), (2, 2 * 3600), (3, 3 * 3600)] {
+            let file = File::create(numbered(&path, number)).unwrap();
+            file.set_modified(SystemTime::now() - Duration::from_secs(age)).unwrap();
+        }
+
+        let mut sink = Sink::new(LogSinkConfig { keep_for_secs: Some(3600), ..config(path.clone(), LogFormat::Jsonl) });
+        sink.write(&record(None, "first"));
+        sink.flush();
+
+        assert_eq!(messages(&path), ["first"]);
+        assert!(numbered(&path, 1).exists());
+        assert!(!numbered(&path, 2).exists());
+        assert!(!numbered(&path, 3).exists());
+    }
+
+    #[test]
+    fn plugins_get_their_own_file() {
+        let dir = test_dir("plugins");
+        let mut sink = Sink::new(config(dir.join("{plugin}.jsonl"), LogFormat::Jsonl));
+        sink.write(&record(None, "host"));
+        sink.write(&record(Some("My/Plugin"), "plugin"));
+        sink.flush();
+        assert_eq!(messages(&dir.join("xtea.jsonl")), ["host"]);
+        assert_eq!(messages(&dir.join("My_Plugin.jsonl")), ["plugin"]);
+
+        let mut sink = Sink::new(LogSinkConfig { plugin: Some("xtea".to_string()), ..config(dir.join("only.jsonl"), LogFormat::Jsonl) });
+        sink.write(&record(None, "host"));
+        sink.write(&record(Some("My/Plugin"), "plugin"));
+        sink.flush();
+        assert_eq!(messages(&dir.join("only.jsonl")), ["host"]);
+    }
+}
@@ -1,17 +1,20 @@
-use std::{time::Instant, collections::HashMap, ffi::OsString, path::{Path, PathBuf}, thread::{Thread, JoinHandle}};
+use std::{path::{Path, PathBuf}, time::Instant};
 
-use log::{error, trace};
-use xtealib::ImguiLogger;
-use imgui::{Context, Ui, sys::{ImGuiMemAllocFunc, ImGuiMemFreeFunc}};
+use cli::{Args, Geometry, USAGE};
+use config::Config;
+use plugins::{LoadFilter, Plugins, discovery::SearchPaths, manifest::Manifest};
+use xtealib::{ImguiLogger, LogFilter, LogFilters};
+use imgui::Context;
 use imgui_wgpu::{Renderer, RendererConfig};
 use imgui_winit_support::WinitPlatform;
 use wgpu::{InstanceDescriptor, Device, Queue, Surface, CommandEncoder, SurfaceConfiguration};
 use winit::{window::{self, Window}, dpi, event_loop::{EventLoop, ControlFlow}, event::{Event, WindowEvent}};
 use image::io::Reader as ImageReader;
-#[macro_use]
-extern crate dlopen_derive;
-use dlopen::wrapper::{Container, WrapperApi};
-
+use xtea_raster::{DrawData, Rasterizer};
+mod cli;
+mod config;
+mod logs;
+mod plugins;
 mod ui;
 
 const ICON_PATH: &str = "icon.png";
@@ -20,186 +23,51 @@ const WIN_NAME: &str = "Xtea.png";
 const SCREEN_HEIGHT: u32 = 200;
 const SCREEN_WIDTH: u32 = 500;
 
-static IMGUI_LOGGER: ImguiLogger = ImguiLogger::new();
-
-#[derive(WrapperApi)]
-struct PluginApi {
-    init_logger: extern fn(logger: &'static ImguiLogger),
-    init_imgui: extern fn(ctx: *mut imgui::sys::ImGuiContext, malloc: ImGuiMemAllocFunc, free: ImGuiMemFreeFunc),
-    init_plugin: extern fn(),
-    build_ui: extern fn(ui: &Ui),
-    view_submenu: extern fn(ui: &Ui),
-    get_name: extern fn() -> String
-}
-
-type Plugin = Container<PluginApi>;
-
-struct Plugins {
-    all_plugins: Vec<(String, PathBuf)>,
-    loaded_plugins: HashMap<String, Plugin>,
-    ui_build_order: Vec<String>,
-    view_submenu_order: Vec<String>
-}
-
-impl Plugins {
-    fn new() -> Plugins {
-        Plugins {
-            all_plugins: Vec::new(),
-            loaded_plugins: HashMap::new(),
-            ui_build_order: Vec::new(),
-            view_submenu_order: Vec::new()
-        }
-    }
-
-    fn load_all(&mut self) {
-        let mut plugins_vec = Vec::new();
-
-        let files = std::fs::read_dir("plugins").unwrap();
-        for file in files {
-            let file = file.unwrap();
-            let filename = file.file_name();
-            let extension = filename
-                .to_str()
-                .unwrap()
-                .split(".")
-                .last();
-            if let Some("dll") = extension {
-                let filename = Path::new("plugins").join(filename);
-                let plugin = unsafe{ Container::<PluginApi>::load(&filename) };
-                match plugin {
-                    Ok(plugin) => plugins_vec.push((plugin, filename)),
-                    Err(e) => {
-                        error!("Failed to load plugin {}\n\t{}", file.file_name().to_str().unwrap(), e);
-                    },
-                }
-            }
-        };
-
-
-        let mut loading_plugins = Vec::new();
-        for (plugin, file_name) in plugins_vec {
-            // TODO: load the orders from a file
-            let name = plugin.get_name();
-            self.all_plugins.push((name.clone(), file_name));
-            loading_plugins.push((name, init_plugin(plugin)));
-        };
-
-        for (name, plugin_thread) in loading_plugins {
-            match plugin_thread.join() {
-                Ok(plugin) => {
-                    self.loaded_plugins.insert(name.clone(), plugin);
-                    self.ui_build_order.push(name.clone());
-                    self.view_submenu_order.push(name);
-                },
-                Err(e) => {
-                    error!("Unkown plugin crashed during initialization\n{e:?}")
-                }
-            }
-        }
-    }
-    fn ui_build_iter(&self) -> impl Iterator<Item=&Plugin> {
-        self.ui_build_order.iter().filter_map(|name| self.loaded_plugins.get(name))
-    }
+/// Environment variable with a log filter, replacing the one of the config.
+const LOG_ENV_VAR: &str = "XTEA_LOG";
 
-    fn view_submenu_iter(&self) -> impl Iterator<Item=&Plugin> {
-        self.view_submenu_order.iter().filter_map(|name| self.loaded_plugins.get(name))
-    }
-
-    fn reload_all_plugins(&mut self) {
-        // To avoid cloning the self.all_plugins
-        let mut tmp_vec = vec![];
-        std::mem::swap(&mut self.all_plugins, &mut tmp_vec);
-
-        let mut activating_plugins = Vec::new();
-        for (plugin_name, dll) in tmp_vec.iter() {
-            if let Some(plugin_thread) = self.reload_plugin_dll(plugin_name, dll) {
-                activating_plugins.push(plugin_thread)
-            };
-        }
-
-        self.wait_init_plugins(activating_plugins);
-
-        std::mem::swap(&mut self.all_plugins, &mut tmp_vec);
-    }
-
-    fn reload_plugin(&mut self, name: &str) -> Option<JoinHandle<Plugin>> {
-        let (name, dll_path) = self.all_plugins.iter().find(|(plugin_name, _)| name == plugin_name).unwrap().clone();
-
-        self.reload_plugin_dll(&name, &dll_path)
-    }
+static IMGUI_LOGGER: ImguiLogger = ImguiLogger::new();
 
-    #[must_use]
-    fn reload_plugin_dll(&mut self, name: &str, dll_path: &PathBuf) -> Option<JoinHandle<Plugin>> {
-        if let Some(plugin) = self.loaded_plugins.remove(name) {
-            std::mem::drop(plugin);
-            let plugin = unsafe{ Container::<PluginApi>::load(dll_path) };
-            match plugin {
-                Ok(plugin) => {
-                    Some(init_plugin(plugin))
-                },
-                Err(e) => {
-                    error!("Failed to reload plugin {} ({})\n\t{}", dll_path.to_str().unwrap(), name, e);
-                    None
-                },
-            }
-        } else {
-            None
-        }
+fn main() {
+    let args = match Args::parse() {
+        Ok(args) => args,
+        Err(e) => {
+            eprintln!("xtea: {e}\nRun `xtea --help` to see the options");
+            std::process::exit(2)
+        },
+    };
+    if args.help {
+        println!("{USAGE}");
+        return
     }
-
-    fn unload(&mut self, plugin_name: &str) {
-        self.loaded_plugins.remove(plugin_name);
+    if args.version {
+        println!("xtea {}", env!("CARGO_PKG_VERSION"));
+        return
     }
 
-    #[must_use]
-    fn activate(&mut self, dll_path: &PathBuf) -> Option<JoinHandle<Plugin>> {
-        let plugin = unsafe{ Container::<PluginApi>::load(dll_path) };
-            match plugin {
-                Ok(plugin) => {
-                    Some(init_plugin(plugin))
-                },
-                Err(e) => {
-                    error!("Failed to load plugin {} \n\t{}", dll_path.to_str().unwrap(), e);
-                    None
-                },
-            }
+    IMGUI_LOGGER.set_stderr(args.log_stderr);
+    // Until the config is read
+    IMGUI_LOGGER.init_with_filter(log_filters(&args, &Config::default()).global).unwrap();
+    let app_config = Config::load(args.config.clone().or_else(Config::default_path));
+    IMGUI_LOGGER.set_filters(log_filters(&args, &app_config));
+    let search_paths = SearchPaths::new(args.plugin_dirs);
+    if args.list_plugins {
+        list_plugins(&search_paths, &app_config);
+        return
     }
-
-    fn wait_init_plugins(&mut self, activating_plugins: Vec<JoinHandle<Plugin>>) {
-        for plugin_thread in activating_plugins {
-            self.wait_init(plugin_thread)
+    if let Some(path) = &args.screenshot {
+        let (mut context, rasterizer) = headless_context(args.geometry);
+        let plugins = start_plugins(search_paths, &app_config, args.load_filter, args.files);
+        let ui_state = ui::UiState::new(&app_config.logs);
+        if let Err(e) = take_screenshot(path, args.frames, &mut context, &rasterizer, ui_state, plugins) {
+            eprintln!("xtea: {e}");
+            std::process::exit(1)
         }
+        return
     }
 
-    fn wait_init(&mut self, activating_plugin: JoinHandle<Plugin>) {
-        match activating_plugin.join() {
-            Ok(plugin) => {
-                self.loaded_plugins.insert(plugin.get_name(), plugin);
-            },
-            Err(e) => {
-                error!("Unkown plugin crashed during initialization\n{e:?}")
-            },
-        }
-    }
-}
-
-fn init_plugin(plugin: Plugin) -> JoinHandle<Plugin> {
-    let ctx = unsafe {imgui::sys::igGetCurrentContext()};
-    let malloc = &mut None;
-    let free = &mut None;
-    let user_data = &mut std::ptr::null_mut();
-    unsafe {imgui::sys::igGetAllocatorFunctions(malloc, free, user_data)};
-    plugin.init_logger(&IMGUI_LOGGER);
-    plugin.init_imgui(ctx, *malloc, *free);
-    std::thread::spawn(|| {plugin.init_plugin(); plugin})
-}
-
-fn main() {
-    if true {
-        IMGUI_LOGGER.init().unwrap();
-    }
     let event_loop = EventLoop::new();
-    let window = make_window(&event_loop);
+    let window = make_window(&event_loop, args.geometry);
 
     let mut context = Context::create();
     let mut platform = imgui_winit_support::WinitPlatform::init(&mut context);
@@ -214,15 +82,114 @@ fn main() {
 
     let renderer = Renderer::new(&mut context, &device, &queue, renderer_config);
 
-    let mut plugins = Plugins::new();
-    plugins.load_all();
-    let mut state = State::new(window, platform, context, surface, device, renderer, queue, config, plugins);
+    let plugins = start_plugins(search_paths, &app_config, args.load_filter, args.files);
+    let mut state = State::new(window, platform, context, surface, device, renderer, queue, config, plugins, app_config);
 
     event_loop.run(move |event,_window_target,control_flow| {
         state.run_event_loop(event, control_flow)
     });
 }
 
+/// The log filters of the config, where `XTEA_LOG` and then the command line replace the one of xtea.
+fn log_filters(args: &Args, config: &Config) -> LogFilters {
+    let mut filters = config.logs.filters();
+    let env_filter = std::env::var(LOG_ENV_VAR).ok()
+        .and_then(|filter| filter.parse::<LogFilter>().map_err(|e| log::error!("Invalid {LOG_ENV_VAR}\n\t{e}")).ok());
+    if let Some(filter) = args.log_filter.clone().or(env_filter) {
+        filters.global = filter
+    }
+    if let Some(level) = args.log_level {
+        filters.global.set_default_level(level)
+    }
+
+    filters
+}
+
+/// Loads the plugins, they connect to the current imgui context.
+fn start_plugins(search_paths: SearchPaths, config: &Config, load_filter: LoadFilter, files: Vec<PathBuf>) -> Plugins {
+    let mut plugins = Plugins::new(search_paths, config.plugins.clone());
+    plugins.set_load_filter(load_filter);
+    plugins.load_all();
+    plugins.open_files(files);
+
+    plugins
+}
+
+/// An imgui context drawn on the CPU, the size of the window.
+fn headless_context(geometry: Option<Geometry>) -> (Context, Rasterizer) {
+    let mut context = Context::create();
+    // The same layout every time, whatever was saved
+    context.set_ini_filename(None);
+    let (width, height) = geometry.map_or((SCREEN_WIDTH, SCREEN_HEIGHT), |geometry| (geometry.width, geometry.height));
+    context.io_mut().display_size = [width as f32, height as f32];
+    context.io_mut().delta_time = 1.0 / 60.0;
+    context.io_mut().config_flags |= imgui::ConfigFlags::DOCKING_ENABLE;
+    let rasterizer = Rasterizer::new(&mut context);
+
+    (context, rasterizer)
+}
+
+/// Runs `frames` frames once the plugins are initialized, and draws the last one into the PNG at `path`.
+fn take_screenshot(path: &Path, frames: usize, context: &mut Context, rasterizer: &Rasterizer, mut ui_state: ui::UiState, mut plugins: Plugins) -> Result<(), String> {
+    plugins.wait_init();
+    let mut draw_data = DrawData::default();
+    for _ in 0..frames {
+        plugins.finish_init();
+        let ui = context.new_frame();
+        ui::create_ui(ui, &mut ui_state, &mut plugins);
+        draw_data = DrawData::capture(context.render());
+    }
+    plugins.unload_all();
+    ui_state.flush_logs();
+
+    rasterizer.render(&draw_data).save(path).map_err(|e| format!("could not save the screenshot to {}: {e}", path.display()))
+}
+
+/// Prints the plugins in the search directories, without loading them.
+fn list_plugins(search_paths: &SearchPaths, config: &Config) {
+    let candidates = search_paths.discover();
+    if candidates.is_empty() {
+        println!("No plugins found in:");
+        for dir in search_paths.dirs() {
+            println!("    {} ({})", dir.path.display(), dir.origin)
+        }
+        return
+    }
+
+    for candidate in candidates {
+        let manifest = Manifest::for_library(&candidate.path, candidate.in_subfolder);
+        let library = candidate.path.file_stem().unwrap_or_default().to_string_lossy();
+        let name = match &manifest {
+            Ok(Some(manifest)) => manifest.name.as_str(),
+            _ => &library,
+        };
+        let disabled = config.plugins.known.values().any(|known| !known.enabled && known.library == candidate.path);
+        println!("{name}{}", if disabled { " (disabled)" } else { "" });
+        println!("    {} ({})", candidate.path.display(), candidate.origin);
+        match manifest {
+            Ok(Some(manifest)) => {
+                if let Some(id) = &manifest.id {
+                    println!("    ID: {id}")
+                }
+                if let Some(version) = &manifest.version {
+                    println!("    Version: {version}")
+                }
+                if !manifest.authors.is_empty() {
+                    println!("    Authors: {}", manifest.authors.join(", "))
+                }
+                if let Some(description) = &manifest.description {
+                    println!("    {description}")
+                }
+                if let Err(reason) = manifest.check_compatibility() {
+                    println!("    Incompatible: {reason}")
+                }
+            },
+            Ok(None) => println!("    No manifest"),
+            Err(e) => println!("    {e}"),
+        }
+    }
+}
+
 async fn init_gpu(window: &Window) -> (Device, Queue, SurfaceConfiguration, Surface) {
     let backends = wgpu::Backends::all().difference(wgpu::Backends::DX12);
     let instance = wgpu::Instance::new(InstanceDescriptor {
@@ -280,21 +247,25 @@ async fn init_gpu(window: &Window) -> (Device, Queue, SurfaceConfiguration, Surf
     (device, queue, config, surface)
 }
 
-fn make_window(event_loop: &EventLoop<()>) -> Window {
+fn make_window(event_loop: &EventLoop<()>, geometry: Option<Geometry>) -> Window {
     let icon = match ImageReader::open(ICON_PATH).unwrap().decode() {
         Ok(img) => Ok(img.to_rgba8()),
         Err(_) => Err(InitError::Unkown),
     }.unwrap();
 
-    let wb = window::WindowBuilder::new()
+    let (width, height) = geometry.map_or((SCREEN_WIDTH, SCREEN_HEIGHT), |geometry| (geometry.width, geometry.height));
+    let mut wb = window::WindowBuilder::new()
         .with_title(WIN_NAME)
-        .with_inner_size(dpi::LogicalSize::new(SCREEN_WIDTH, SCREEN_HEIGHT))
+        .with_inner_size(dpi::LogicalSize::new(width, height))
         .with_window_icon(Some(match window::Icon::from_rgba(icon.into_raw(), 64, 64) {
             Ok(icon) => icon,
             Err(_) => panic!("Couldn't get icon raw data")
         }));
+    if let Some((x, y)) = geometry.and_then(|geometry| geometry.position) {
+        wb = wb.with_position(dpi::LogicalPosition::new(x, y))
+    }
 
-    wb.build(&event_loop)
+    wb.build(event_loop)
         .unwrap()
 }
 
@@ -314,11 +285,13 @@ struct State {
     config: SurfaceConfiguration,
     ui_state: ui::UiState,
     plugins: Plugins,
+    app_config: Config,
     last_render_time: Instant,
 }
 
 impl State {
-    fn new(window: Window, platform: WinitPlatform, mut context: Context, surface: Surface, device: Device, renderer: Renderer, queue: Queue, config: SurfaceConfiguration, plugins: Plugins) -> State {
+    #[allow(clippy::too_many_arguments)]
+    fn new(window: Window, platform: WinitPlatform, mut context: Context, surface: Surface, device: Device, renderer: Renderer, queue: Queue, config: SurfaceConfiguration, plugins: Plugins, app_config: Config) -> State {
         context.io_mut().config_flags |= imgui::ConfigFlags::DOCKING_ENABLE;
         State {
             window,
@@ -330,12 +303,13 @@ impl State {
             queue,
             config,
             plugins,
-            ui_state: ui::UiState::new(),
+            ui_state: ui::UiState::new(&app_config.logs),
+            app_config,
             last_render_time: Instant::now()
         }
     }
 
-    fn run_event_loop<'a>(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
+    fn run_event_loop(&mut self, event: Event<()>, control_flow: &mut ControlFlow) {
         *control_flow = ControlFlow::Poll;
         self.platform.handle_event(self.context.io_mut(), &self.window, &event);
         match event {
@@ -344,7 +318,13 @@ impl State {
                     WindowEvent::Resized(size) => {
                         self.resize(size)
                     },
-                    WindowEvent::CloseRequested => *control_flow = ControlFlow::Exit, //control_flow is a pointer to the next action we wanna do. In this case, exit the program
+                    WindowEvent::CloseRequested => {
+                        self.save_config();
+                        // The event loop exits the process without dropping the state
+                        self.plugins.unload_all();
+                        self.ui_state.flush_logs();
+                        *control_flow = ControlFlow::Exit //control_flow is a pointer to the next action we wanna do. In this case, exit the program
+                    },
                     WindowEvent::ScaleFactorChanged { scale_factor: _, new_inner_size } => {
                         self.resize(*new_inner_size)
                     },
@@ -355,7 +335,7 @@ impl State {
             Event::MainEventsCleared => {
                 self.window.request_redraw()
             },
-            Event::RedrawRequested(window_id) => if window_id == self.window.id() {
+            Event::RedrawRequested(window_id) if window_id == self.window.id() => {
                 let now = std::time::Instant::now();
                 //let dt = now - self.last_render_time;
                 self.last_render_time = now;
@@ -378,9 +358,14 @@ impl State {
         view: &wgpu::TextureView,
     ) -> CommandEncoder {
         self.platform.prepare_frame(self.context.io_mut(), &self.window).expect("Failed to prepare frame");
+        self.plugins.reload_changed();
+        self.plugins.finish_init();
         let ui = self.context.frame();
 
-        ui::create_ui(&ui, &mut self.ui_state, &mut self.plugins);
+        ui::create_ui(ui, &mut self.ui_state, &mut self.plugins);
+        if self.plugins.take_config_changed() {
+            self.save_config()
+        }
 
         let mut encoder = self.device.create_command_encoder(&wgpu::CommandEncoderDescriptor {
             label: Some("ImGui Render Encoder")
@@ -389,7 +374,7 @@ impl State {
             let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
                 label: None,
                 color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &view,
+                    view,
                     resolve_target: None,
                     ops: wgpu::Operations {
                         load: wgpu::LoadOp::Load,
@@ -403,6 +388,11 @@ impl State {
         encoder
     }
 
+    fn save_config(&mut self) {
+        self.app_config.plugins = self.plugins.config();
+        self.app_config.save()
+    }
+
     fn resize(&mut self, new_size: dpi::PhysicalSize<u32>) {
         self.config.width = new_size.width;
         self.config.height = new_size.height;
@@ -0,0 +1,1030 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt::Display,
+    path::{Path, PathBuf},
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use imgui::Ui;
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use xtealib::LogFilters;
+
+use crate::config::{PluginConfig, PluginsConfig, RestartPolicy};
+
+use self::{discovery::{Candidate, SearchOrigin, SearchPaths}, library::LoadError, manifest::Manifest, watcher::PluginWatcher};
+pub use self::library::Plugin;
+
+pub mod discovery;
+mod install;
+pub mod library;
+pub mod manifest;
+mod shadow;
+pub mod watcher;
+
+/// Identifies a plugin in all of xtea's bookkeeping. Unlike its name, no two plugins share it.
+///
+/// It's the manifest's `id` if it has one, so it survives moving the library,
+/// otherwise the plugin name and its library path.
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct PluginId(String);
+
+impl PluginId {
+    fn new(name: &str, manifest: Option<&Manifest>, library: &Path) -> PluginId {
+        match manifest.and_then(|manifest| manifest.id.as_ref()) {
+            Some(id) => PluginId(id.clone()),
+            None => PluginId::of_library(name, library),
+        }
+    }
+
+    fn of_library(name: &str, library: &Path) -> PluginId {
+        PluginId(format!("{name}@{}", library.display()))
+    }
+}
+
+impl Display for PluginId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// A discovered plugin library.
+pub struct PluginEntry {
+    pub id: PluginId,
+    pub name: String,
+    pub path: PathBuf,
+    pub origin: SearchOrigin,
+    /// Whether the library lives in its own plugin subfolder.
+    pub in_subfolder: bool,
+    pub manifest: Option<Manifest>,
+    pub status: PluginStatus,
+    /// The latest error, kept after the plugin recovers from it.
+    pub last_error: Option<String>,
+    /// How long opening its library and creating its instance took, the last time it was loaded.
+    pub load_time: Option<Duration>,
+    pub init_time: Option<Duration>,
+    /// How many times this plugin was restarted automatically.
+    restarts: u32,
+}
+
+impl PluginEntry {
+    fn new(id: PluginId, name: String, candidate: &Candidate, manifest: Option<Manifest>, status: PluginStatus) -> PluginEntry {
+        PluginEntry {
+            id,
+            name,
+            path: candidate.path.clone(),
+            origin: candidate.origin,
+            in_subfolder: candidate.in_subfolder,
+            manifest,
+            last_error: status.reason().map(str::to_string),
+            status,
+            load_time: None,
+            init_time: None,
+            restarts: 0,
+        }
+    }
+}
+
+/// Where a plugin is in its lifecycle.
+#[derive(Debug, Clone)]
+pub enum PluginStatus {
+    /// Found, but it was never enabled.
+    Discovered,
+    /// Its library is being opened.
+    Loading,
+    /// Its instance is being created.
+    Initializing,
+    Active,
+    /// It couldn't be loaded or initialized, for the given reason. It can be retried.
+    Failed(String),
+    /// It was built for another plugin API, another imgui, or another xtea.
+    Incompatible(String),
+    /// It panicked, with the given report. It can be restarted.
+    Crashed(String),
+    /// Another plugin with the same name or ID was found first, this one is ignored.
+    Conflict(String),
+    /// Disabled by the user.
+    Disabled,
+}
+
+impl PluginStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            PluginStatus::Discovered => "not enabled",
+            PluginStatus::Loading => "loading",
+            PluginStatus::Initializing => "initializing",
+            PluginStatus::Active => "active",
+            PluginStatus::Failed(_) => "failed",
+            PluginStatus::Incompatible(_) => "incompatible",
+            PluginStatus::Crashed(_) => "crashed",
+            PluginStatus::Conflict(_) => "conflict",
+            PluginStatus::Disabled => "disabled",
+        }
+    }
+
+    /// Why the plugin isn't running, if it's because of an error.
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            PluginStatus::Failed(reason)
+            | PluginStatus::Incompatible(reason)
+            | PluginStatus::Crashed(reason)
+            | PluginStatus::Conflict(reason) => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// A plugin being created in its own thread.
+pub struct Initializing {
+    id: PluginId,
+    started: Instant,
+    thread: JoinHandle<(Plugin, Result<(), String>)>,
+    /// Whether it was already reported for taking longer than the timeout.
+    timed_out: bool,
+}
+
+impl Initializing {
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    pub fn timed_out(&self) -> bool {
+        self.timed_out
+    }
+}
+
+/// Which plugins to load in this run, from the command line. Never saved to the config.
+#[derive(Debug, Clone, Default)]
+pub struct LoadFilter {
+    /// Don't load any plugin.
+    pub safe_mode: bool,
+    /// If set, only load these plugins, by name, ID or library name.
+    pub only: Option<Vec<String>>,
+    /// Don't load these plugins, by name, ID or library name.
+    pub disable: Vec<String>,
+}
+
+impl LoadFilter {
+    /// Whether the plugin known by `names` should be loaded.
+    fn allows(&self, names: &[&str]) -> bool {
+        !self.excludes(names) && self.only.as_deref().is_none_or(|only| listed(only, names))
+    }
+
+    /// Whether the plugin known by `names` mustn't be loaded, whatever its other names.
+    /// Unlike [`allows`](LoadFilter::allows), true from any of its names.
+    fn excludes(&self, names: &[&str]) -> bool {
+        self.safe_mode || listed(&self.disable, names)
+    }
+}
+
+fn listed(list: &[String], names: &[&str]) -> bool {
+    list.iter().any(|listed| names.contains(&listed.as_str()))
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum PluginOrder {
+    UiBuild,
+    ViewSubmenu,
+}
+
+pub struct Plugins {
+    search_paths: SearchPaths,
+    pub all_plugins: Vec<PluginEntry>,
+    pub loaded_plugins: HashMap<PluginId, Plugin>,
+    initializing: Vec<Initializing>,
+    /// How long a plugin can take to initialize before it's reported.
+    init_timeout: Duration,
+    /// How long an unloaded plugin has to stop its tasks, during which xtea waits.
+    shutdown_timeout: Duration,
+    ui_build_order: Vec<PluginId>,
+    view_submenu_order: Vec<PluginId>,
+    restart_policy: RestartPolicy,
+    known: BTreeMap<PluginId, PluginConfig>,
+    config_changed: bool,
+    watcher: Option<PluginWatcher>,
+    load_filter: LoadFilter,
+    /// Files from the command line, given to the plugins once they're all initialized.
+    pending_files: Vec<PathBuf>,
+}
+
+impl Plugins {
+    pub fn new(search_paths: SearchPaths, config: PluginsConfig) -> Plugins {
+        shadow::remove_stale_shadow_dirs();
+        Plugins {
+            search_paths,
+            all_plugins: Vec::new(),
+            loaded_plugins: HashMap::new(),
+            initializing: Vec::new(),
+            init_timeout: Duration::from_secs(config.init_timeout_secs),
+            shutdown_timeout: Duration::from_millis(config.shutdown_timeout_ms),
+            ui_build_order: config.ui_build_order,
+            view_submenu_order: config.view_submenu_order,
+            restart_policy: config.restart,
+            known: config.known,
+            config_changed: false,
+            watcher: None,
+            load_filter: LoadFilter::default(),
+            pending_files: Vec::new(),
+        }
+    }
+
+    pub fn set_load_filter(&mut self, load_filter: LoadFilter) {
+        self.load_filter = load_filter
+    }
+
+    /// Gives each file to the first plugin, in build order, that opens it.
+    pub fn open_files(&mut self, files: Vec<PathBuf>) {
+        self.pending_files.extend(files)
+    }
+
+    pub fn config(&self) -> PluginsConfig {
+        PluginsConfig {
+            ui_build_order: self.ui_build_order.clone(),
+            view_submenu_order: self.view_submenu_order.clone(),
+            restart: self.restart_policy,
+            init_timeout_secs: self.init_timeout.as_secs(),
+            shutdown_timeout_ms: self.shutdown_timeout.as_millis() as u64,
+            known: self.known.clone(),
+        }
+    }
+
+    pub fn restart_policy(&self) -> RestartPolicy {
+        self.restart_policy
+    }
+
+    pub fn set_restart_policy(&mut self, restart_policy: RestartPolicy) {
+        self.restart_policy = restart_policy;
+        self.config_changed = true
+    }
+
+    /// Whether the plugin config changed since the last call.
+    pub fn take_config_changed(&mut self) -> bool {
+        std::mem::take(&mut self.config_changed)
+    }
+
+    fn set_enabled(&mut self, id: &PluginId, library: &Path, enabled: bool) {
+        match self.known.get_mut(id) {
+            Some(known) if known.enabled == enabled && known.library == library => (),
+            Some(known) => {
+                known.enabled = enabled;
+                known.library = library.to_path_buf();
+                self.config_changed = true
+            },
+            None => {
+                let name = self.entry(id).map(|entry| entry.name.clone()).unwrap_or_default();
+                self.known.insert(id.clone(), PluginConfig {
+                    name,
+                    enabled,
+                    library: library.to_path_buf(),
+                    auto_reload: true,
+                });
+                self.config_changed = true
+            },
+        }
+    }
+
+    pub fn auto_reload(&self, id: &PluginId) -> bool {
+        self.known.get(id).is_none_or(|known| known.auto_reload)
+    }
+
+    pub fn set_auto_reload(&mut self, id: &PluginId, auto_reload: bool) {
+        if let Some(known) = self.known.get_mut(id) {
+            known.auto_reload = auto_reload;
+            self.config_changed = true
+        }
+    }
+
+    /// Reloads the loaded plugins whose library was rebuilt, unless their auto reload is off.
+    pub fn reload_changed(&mut self) {
+        let Some(watcher) = self.watcher.as_mut() else {
+            return
+        };
+
+        for path in watcher.changed_libraries() {
+            let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+            let entry = self.all_plugins.iter()
+                .find(|entry| entry.path.canonicalize().is_ok_and(|entry_path| entry_path == canonical));
+            let Some(id) = entry.map(|entry| entry.id.clone()) else {
+                continue
+            };
+            if !self.loaded_plugins.contains_key(&id) || !self.auto_reload(&id) {
+                continue
+            }
+
+            info!("Library {} changed, reloading plugin {id}", path.display());
+            self.reload_plugin(&id)
+        }
+    }
+
+    pub fn order(&self, order: PluginOrder) -> &[PluginId] {
+        match order {
+            PluginOrder::UiBuild => &self.ui_build_order,
+            PluginOrder::ViewSubmenu => &self.view_submenu_order,
+        }
+    }
+
+    /// Swaps the plugin at `index` with the next one.
+    pub fn move_down(&mut self, order: PluginOrder, index: usize) {
+        let order = match order {
+            PluginOrder::UiBuild => &mut self.ui_build_order,
+            PluginOrder::ViewSubmenu => &mut self.view_submenu_order,
+        };
+        if index + 1 < order.len() {
+            order.swap(index, index + 1);
+            self.config_changed = true
+        }
+    }
+
+    /// Drops uninstalled plugins from the orders, and appends the new ones.
+    fn sync_orders(&mut self) {
+        let installed = self.all_plugins.iter()
+            .filter(|entry| !matches!(entry.status, PluginStatus::Conflict(_)))
+            .map(|entry| entry.id.clone())
+            .collect::<Vec<_>>();
+        for order in [&mut self.ui_build_order, &mut self.view_submenu_order] {
+            let old_len = order.len();
+            order.retain(|id| installed.contains(id));
+            let mut changed = order.len() != old_len;
+            for id in installed.iter() {
+                if !order.contains(id) {
+                    order.push(id.clone());
+                    changed = true
+                }
+            }
+            self.config_changed |= changed
+        }
+    }
+
+    /// Lists every plugin in the search directories, and loads the enabled ones.
+    /// Plugins that are already listed are left alone.
+    pub fn load_all(&mut self) {
+        if self.watcher.is_none() {
+            self.watcher = PluginWatcher::new(self.search_paths.dirs())
+        }
+
+        for candidate in self.search_paths.discover() {
+            if self.all_plugins.iter().any(|entry| entry.path == candidate.path) {
+                continue
+            }
+
+            let manifest = match Manifest::for_library(&candidate.path, candidate.in_subfolder) {
+                Ok(manifest) => manifest,
+                Err(reason) => {
+                    // Without its manifest, the plugin can't be checked, so it isn't loaded until it's fixed
+                    error!("Refusing to load plugin {}\n\t{reason}", candidate.path.display());
+                    let library = library_name(&candidate.path);
+                    let id = PluginId::of_library(&library, &candidate.path);
+                    if !self.conflict(&id, &library, &candidate, &None) {
+                        self.all_plugins.push(PluginEntry::new(id, library, &candidate, None, PluginStatus::Incompatible(reason)));
+                    }
+                    continue
+                },
+            };
+
+            // Plugins disabled by the user are recognized by their library, so they aren't even opened
+            let disabled = self.known.iter()
+                .find(|(_, known)| !known.enabled && known.library == candidate.path)
+                .map(|(id, known)| (id.clone(), known.name.clone()));
+            if let Some((id, name)) = disabled {
+                let name = if name.is_empty() { library_name(&candidate.path) } else { name };
+                if !self.conflict(&id, &name, &candidate, &manifest) {
+                    info!("Plugin {id} is disabled, not loading it");
+                    self.all_plugins.push(PluginEntry::new(id, name, &candidate, manifest, PluginStatus::Disabled));
+                }
+                continue
+            }
+
+            // Without a manifest, only the library's name is known before opening it, which runs its code
+            let library = library_name(&candidate.path);
+            if manifest.is_none() && self.load_filter.excludes(&[&library]) {
+                let id = PluginId::of_library(&library, &candidate.path);
+                if !self.conflict(&id, &library, &candidate, &manifest) {
+                    info!("Plugin {} is excluded by the command line, not loading it", candidate.path.display());
+                    self.all_plugins.push(PluginEntry::new(id, library, &candidate, manifest, PluginStatus::Discovered));
+                }
+                continue
+            }
+
+            if let Some(manifest) = manifest.as_ref() {
+                let id = PluginId::new(&manifest.name, Some(manifest), &candidate.path);
+                if self.conflict(&id, &manifest.name, &candidate, &Some(manifest.clone())) {
+                    continue
+                }
+                let status = if let Err(reason) = manifest.check_compatibility() {
+                    error!("Refusing to load plugin {}\n\t{reason}", candidate.path.display());
+                    PluginStatus::Incompatible(reason)
+                } else if !self.load_filter.allows(&[&manifest.name, &id.0, &library]) {
+                    info!("Plugin {id} is excluded by the command line, not loading it");
+                    PluginStatus::Discovered
+                } else if !manifest.default_enabled && !self.known.contains_key(&id) {
+                    info!("Plugin {id} is disabled by default, not loading it");
+                    PluginStatus::Discovered
+                } else {
+                    PluginStatus::Loading
+                };
+                if !matches!(status, PluginStatus::Loading) {
+                    self.all_plugins.push(PluginEntry::new(id, manifest.name.clone(), &candidate, Some(manifest.clone()), status));
+                    continue
+                }
+            }
+
+            let plugin = match Plugin::load(&candidate.path, self.shutdown_timeout) {
+                Ok(plugin) => plugin,
+                Err(e) => {
+                    let status = match e {
+                        LoadError::Incompatible(reason) => {
+                            error!("Refusing to load plugin {}\n\t{reason}", candidate.path.display());
+                            PluginStatus::Incompatible(reason)
+                        },
+                        e => {
+                            error!("Failed to load plugin {}\n\t{e}", candidate.path.display());
+                            PluginStatus::Failed(e.to_string())
+                        },
+                    };
+                    let name = match manifest.as_ref() {
+                        Some(manifest) => manifest.name.clone(),
+                        None => library,
+                    };
+                    let id = PluginId::new(&name, manifest.as_ref(), &candidate.path);
+                    if !self.conflict(&id, &name, &candidate, &manifest) {
+                        self.all_plugins.push(PluginEntry::new(id, name, &candidate, manifest, status));
+                    }
+                    continue
+                },
+            };
+
+            let name = plugin.get_name();
+            if let Some(manifest) = manifest.as_ref().filter(|manifest| manifest.name != name) {
+                warn!("Plugin {} calls itself {name}, but its manifest says {}", candidate.path.display(), manifest.name)
+            }
+            let id = PluginId::new(&name, manifest.as_ref(), &candidate.path);
+            if self.conflict(&id, &name, &candidate, &manifest) {
+                continue
+            }
+            // The library moved since it was disabled
+            if self.known.get(&id).is_some_and(|known| !known.enabled) {
+                info!("Plugin {id} is disabled, not loading it");
+                self.all_plugins.push(PluginEntry::new(id, name, &candidate, manifest, PluginStatus::Disabled));
+                continue
+            }
+            // Its name can also be the one excluded, or the one `--only` lists
+            if !self.load_filter.allows(&[&name, &id.0, &library]) {
+                info!("Plugin {id} is excluded by the command line, not loading it");
+                self.all_plugins.push(PluginEntry::new(id, name, &candidate, manifest, PluginStatus::Discovered));
+                continue
+            }
+
+            info!("Loading plugin {id} ({})", candidate.origin);
+            self.all_plugins.push(PluginEntry::new(id.clone(), name, &candidate, manifest, PluginStatus::Loading));
+            self.start_init(id, plugin, None);
+        };
+
+        self.sync_orders();
+    }
+
+    /// Forgets the plugins whose library was removed, and lists the new ones.
+    /// Incompatible plugins are checked again, their manifest or library may have been fixed.
+    pub fn rescan(&mut self) {
+        let removed = self.all_plugins.iter()
+            .filter(|entry| !entry.path.exists() && !self.loaded_plugins.contains_key(&entry.id))
+            .map(|entry| entry.id.clone())
+            .collect::<Vec<_>>();
+        for id in removed.iter() {
+            info!("Plugin {id} was removed");
+        }
+        self.all_plugins.retain(|entry| {
+            !removed.contains(&entry.id) && !matches!(entry.status, PluginStatus::Incompatible(_))
+        });
+
+        // Directories that didn't exist before can be watched now
+        self.watcher = None;
+        self.load_all()
+    }
+
+    /// Copies a plugin library or folder into the user plugin directory, and enables it.
+    pub fn install(&mut self, source: &Path) -> Result<(), String> {
+        let Some(user_dir) = self.search_paths.user_dir().map(Path::to_path_buf) else {
+            return Err("There is no user plugin directory".to_string())
+        };
+        let library = install::install(source, &user_dir)?;
+        info!("Installed plugin {} to {}", source.display(), library.display());
+
+        self.rescan();
+        let installed = self.all_plugins.iter().find(|entry| entry.path == library);
+        match installed {
+            Some(entry) if matches!(entry.status, PluginStatus::Discovered | PluginStatus::Disabled) => {
+                let id = entry.id.clone();
+                self.activate(&id);
+                Ok(())
+            },
+            Some(_) => Ok(()),
+            None => Err(format!("{} was installed, but it wasn't found in {}", library.display(), user_dir.display())),
+        }
+    }
+
+    /// Unloads a plugin and deletes its files.
+    pub fn uninstall(&mut self, id: &PluginId) -> Result<(), String> {
+        if self.initializing(id).is_some() {
+            return Err(format!("Plugin {id} is initializing, it can't be uninstalled yet"))
+        }
+        let Some(index) = self.all_plugins.iter().position(|entry| &entry.id == id) else {
+            return Err(format!("Plugin {id} isn't installed"))
+        };
+
+        if self.loaded_plugins.remove(id).is_some() {
+            // Stays listed as disabled if its files can't be removed
+            self.set_status(id, PluginStatus::Disabled)
+        }
+        let entry = &self.all_plugins[index];
+        install::uninstall(&entry.path, entry.in_subfolder)?;
+        info!("Uninstalled plugin {id}");
+
+        self.all_plugins.remove(index);
+        self.config_changed |= self.known.remove(id).is_some();
+        self.sync_orders();
+        Ok(())
+    }
+
+    /// If a plugin with the same name or ID was already found, lists `candidate` as a conflict and returns true.
+    fn conflict(&mut self, id: &PluginId, name: &str, candidate: &Candidate, manifest: &Option<Manifest>) -> bool {
+        let first = self.all_plugins.iter()
+            .filter(|entry| !matches!(entry.status, PluginStatus::Conflict(_)))
+            .find(|entry| entry.name == name || &entry.id == id);
+        let Some(first) = first else {
+            return false
+        };
+
+        let reason = if first.name == name {
+            format!("a plugin called {name} was already found in {} ({})", first.path.display(), first.origin)
+        } else {
+            format!("a plugin with ID {id} was already found in {} ({})", first.path.display(), first.origin)
+        };
+        error!("Refusing to load plugin {} ({})\n\t{reason}", candidate.path.display(), candidate.origin);
+        // Its ID may be the same as the other plugin's, but its library isn't
+        let id = PluginId::of_library(name, &candidate.path);
+        let status = PluginStatus::Conflict(reason);
+        self.all_plugins.push(PluginEntry::new(id, name.to_string(), candidate, manifest.clone(), status));
+
+        true
+    }
+
+    pub fn entry(&self, id: &PluginId) -> Option<&PluginEntry> {
+        self.all_plugins.iter().find(|entry| &entry.id == id)
+    }
+
+    fn entry_mut(&mut self, id: &PluginId) -> Option<&mut PluginEntry> {
+        self.all_plugins.iter_mut().find(|entry| &entry.id == id)
+    }
+
+    pub fn search_paths(&self) -> &SearchPaths {
+        &self.search_paths
+    }
+
+    pub fn build_ui(&mut self, ui: &Ui) {
+        let mut crashed = Vec::new();
+        for id in self.ui_build_order.iter() {
+            if let Some(plugin) = self.loaded_plugins.get_mut(id) {
+                plugin.build_ui(ui);
+                if let Some(report) = plugin.take_panic() {
+                    crashed.push((id.clone(), report))
+                }
+            }
+        }
+
+        for (id, report) in crashed {
+            self.crashed(&id, report)
+        }
+    }
+
+    pub fn view_submenu(&mut self, ui: &Ui) {
+        let mut crashed = Vec::new();
+        for id in self.view_submenu_order.iter() {
+            if let Some(plugin) = self.loaded_plugins.get_mut(id) {
+                plugin.view_submenu(ui);
+                if let Some(report) = plugin.take_panic() {
+                    crashed.push((id.clone(), report))
+                }
+            }
+        }
+
+        for (id, report) in crashed {
+            self.crashed(&id, report)
+        }
+    }
+
+    /// Unloads a plugin that panicked, and restarts it if the restart policy allows it.
+    fn crashed(&mut self, id: &PluginId, report: String) {
+        error!("Plugin {id} crashed and was unloaded\n{report}");
+        drop(self.loaded_plugins.remove(id));
+
+        let Some(entry) = self.all_plugins.iter_mut().find(|entry| &entry.id == id) else {
+            return
+        };
+        entry.last_error = Some(report.clone());
+        entry.status = PluginStatus::Crashed(report);
+        if self.restart_policy.auto_restart && entry.restarts < self.restart_policy.max_restarts {
+            entry.restarts += 1;
+            info!("Restarting plugin {id} ({}/{})", entry.restarts, self.restart_policy.max_restarts);
+            self.activate(id)
+        }
+    }
+
+    /// Replaces the log filters of xtea and of every loaded plugin.
+    pub fn set_log_filters(&mut self, filters: LogFilters) {
+        for plugin in self.loaded_plugins.values() {
+            plugin.set_log_filter(filters.get(Some(&plugin.get_name())))
+        }
+        crate::IMGUI_LOGGER.set_filters(filters)
+    }
+
+    pub fn reload_all_plugins(&mut self) {
+        let ids: Vec<_> = self.all_plugins.iter().map(|entry| entry.id.clone()).collect();
+        for id in ids {
+            self.reload_plugin(&id)
+        }
+    }
+
+    /// Reloads the plugin's library, if the plugin is loaded.
+    pub fn reload_plugin(&mut self, id: &PluginId) {
+        let Some(dll_path) = self.entry(id).map(|entry| entry.path.clone()) else {
+            return
+        };
+        if let Some(mut plugin) = self.loaded_plugins.remove(id) {
+            let state = plugin.save_state();
+            if let Some(report) = plugin.take_panic() {
+                error!("Plugin {id} crashed while saving its state, it won't be kept\n{report}")
+            }
+            std::mem::drop(plugin);
+            self.set_status(id, PluginStatus::Loading);
+            match Plugin::load(&dll_path, self.shutdown_timeout) {
+                Ok(plugin) => self.start_init(id.clone(), plugin, state),
+                Err(e) => {
+                    error!("Failed to reload plugin {id}\n\t{e}");
+                    self.set_status(id, PluginStatus::Failed(e.to_string()))
+                },
+            }
+        }
+    }
+
+    pub fn unload(&mut self, id: &PluginId) {
+        if let Some(plugin) = self.loaded_plugins.remove(id) {
+            self.set_enabled(id, plugin.path(), false);
+            self.set_status(id, PluginStatus::Disabled)
+        }
+    }
+
+    fn set_status(&mut self, id: &PluginId, status: PluginStatus) {
+        if let Some(entry) = self.entry_mut(id) {
+            if let Some(reason) = status.reason() {
+                entry.last_error = Some(reason.to_string())
+            }
+            entry.status = status
+        }
+    }
+
+    /// Unloads every plugin, without disabling them, and removes their library copies.
+    pub fn unload_all(&mut self) {
+        // All at once, so every plugin gets the whole shutdown timeout to stop its tasks
+        std::thread::scope(|scope| {
+            for (_, plugin) in self.loaded_plugins.drain() {
+                scope.spawn(move || drop(plugin));
+            }
+        });
+        for initializing in self.initializing.drain(..) {
+            warn!("Plugin {} is still initializing, abandoning it", initializing.id)
+        }
+        shadow::remove_shadow_dir()
+    }
+
+    /// Loads a plugin that isn't loaded, to enable it, retry it after a failure, or restart it after a crash.
+    pub fn activate(&mut self, id: &PluginId) {
+        let Some(dll_path) = self.entry(id).map(|entry| entry.path.clone()) else {
+            return
+        };
+        if self.loaded_plugins.contains_key(id) || self.initializing(id).is_some() {
+            return
+        }
+        self.set_status(id, PluginStatus::Loading);
+        match Plugin::load(&dll_path, self.shutdown_timeout) {
+            Ok(plugin) => self.start_init(id.clone(), plugin, None),
+            Err(e) => {
+                error!("Failed to load plugin {id}\n\t{e}");
+                self.set_status(id, PluginStatus::Failed(e.to_string()))
+            },
+        }
+    }
+
+    /// Creates the plugin's instance in the background, and gives it `state` if it's being reloaded.
+    fn start_init(&mut self, id: PluginId, plugin: Plugin, state: Option<Vec<u8>>) {
+        self.set_status(&id, PluginStatus::Initializing);
+        self.initializing.push(Initializing {
+            id,
+            started: Instant::now(),
+            thread: init_plugin(plugin, state),
+            timed_out: false,
+        })
+    }
+
+    /// The plugin, if it's being created.
+    pub fn initializing(&self, id: &PluginId) -> Option<&Initializing> {
+        self.initializing.iter().find(|initializing| &initializing.id == id)
+    }
+
+    /// Waits until every plugin is initialized, or reported as taking too long.
+    pub fn wait_init(&mut self) {
+        loop {
+            self.finish_init();
+            if self.initializing.iter().all(|initializing| initializing.timed_out) {
+                return
+            }
+            std::thread::sleep(Duration::from_millis(10))
+        }
+    }
+
+    /// Adds the plugins that finished initializing, and reports the ones taking too long.
+    /// Called every frame.
+    pub fn finish_init(&mut self) {
+        let (finished, initializing) = std::mem::take(&mut self.initializing)
+            .into_iter()
+            .partition::<Vec<_>, _>(|initializing| initializing.thread.is_finished());
+        self.initializing = initializing;
+
+        for initializing in self.initializing.iter_mut() {
+            if !initializing.timed_out && initializing.elapsed() > self.init_timeout {
+                error!(
+                    "Plugin {} didn't initialize in {:?}, it will be added whenever it finishes",
+                    initializing.id, self.init_timeout
+                );
+                initializing.timed_out = true
+            }
+        }
+
+        for initializing in finished {
+            let id = initializing.id;
+            match initializing.thread.join() {
+                Ok((mut plugin, result)) => {
+                    if let Some(entry) = self.entry_mut(&id) {
+                        entry.load_time = Some(plugin.load_time());
+                        entry.init_time = Some(initializing.started.elapsed());
+                    }
+                    if let Some(report) = plugin.take_panic() {
+                        drop(plugin);
+                        self.crashed(&id, report);
+                        continue
+                    }
+                    if let Err(reason) = result {
+                        error!("Plugin {id} failed to initialize\n\t{reason}");
+                        drop(plugin);
+                        self.set_status(&id, PluginStatus::Failed(reason));
+                        continue
+                    }
+                    self.set_status(&id, PluginStatus::Active);
+                    info!("Plugin {id} initialized in {:?}", initializing.started.elapsed());
+                    self.set_enabled(&id, plugin.path(), true);
+                    // In case the filters changed while it was initializing
+                    plugin.set_log_filter(crate::IMGUI_LOGGER.filters().get(Some(&plugin.get_name())));
+                    self.loaded_plugins.insert(id, plugin);
+                },
+                Err(e) => {
+                    error!("Plugin {id} crashed during initialization\n{e:?}");
+                    self.set_status(&id, PluginStatus::Crashed(format!("{e:?}")))
+                },
+            }
+        }
+
+        if self.initializing.is_empty() && !self.pending_files.is_empty() {
+            self.open_pending_files()
+        }
+    }
+
+    fn open_pending_files(&mut self) {
+        for file in std::mem::take(&mut self.pending_files) {
+            let mut opened_by = None;
+            for id in self.ui_build_order.clone() {
+                let Some(plugin) = self.loaded_plugins.get_mut(&id) else {
+                    continue
+                };
+                let opened = plugin.open_file(&file);
+                if let Some(report) = plugin.take_panic() {
+                    self.crashed(&id, report);
+                    continue
+                }
+                if opened {
+                    opened_by = Some(id);
+                    break
+                }
+            }
+            match opened_by {
+                Some(id) => info!("Plugin {id} opened {}", file.display()),
+                None => warn!("No plugin can open {}", file.display()),
+            }
+        }
+    }
+}
+
+/// Name used for a plugin whose name can't be asked to the plugin itself.
+fn library_name(path: &Path) -> String {
+    path.file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_else(|| path.display().to_string())
+}
+
+fn init_plugin(plugin: Plugin, state: Option<Vec<u8>>) -> JoinHandle<(Plugin, Result<(), String>)> {
+    plugin.connect_host();
+    std::thread::spawn(|| {
+        let mut plugin = plugin;
+        let result = plugin.init_plugin();
+        if let (Ok(()), Some(state)) = (&result, state) {
+            plugin.restore_state(&state)
+        }
+        (plugin, result)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-loading-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// A file that looks like a library, but that the dynamic loader fails to open.
+    fn fake_library(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(format!("{name}.{}", std::env::consts::DLL_EXTENSION));
+        std::fs::write(&path, b"not a library").unwrap();
+        path
+    }
+
+    fn plugins(dir: &Path, load_filter: LoadFilter) -> Plugins {
+        let mut plugins = Plugins::new(
+            SearchPaths::with_dirs([(dir.to_path_buf(), SearchOrigin::CommandLine)]),
+            PluginsConfig::default(),
+        );
+        plugins.set_load_filter(load_filter);
+        plugins
+    }
+
+    #[test]
+    fn excluded_libraries_are_never_opened() {
+        let dir = test_dir("excluded");
+        fake_library(&dir, "libcrashy");
+        let mut plugins = plugins(&dir, LoadFilter { disable: vec!["libcrashy".to_string()], ..LoadFilter::default() });
+        plugins.load_all();
+
+        // Opening it would have failed
+        assert_eq!(plugins.all_plugins.len(), 1);
+        assert!(matches!(plugins.all_plugins[0].status, PluginStatus::Discovered), "{:?}", plugins.all_plugins[0].status);
+        assert!(plugins.loaded_plugins.is_empty());
+    }
+
+    #[test]
+    fn only_needs_the_name_of_libraries_without_manifest() {
+        let dir = test_dir("only");
+        fake_library(&dir, "libother");
+        let mut plugins = plugins(&dir, LoadFilter { only: Some(vec!["My Plugin".to_string()]), ..LoadFilter::default() });
+        plugins.load_all();
+
+        // The library had to be opened to know whether it's "My Plugin"
+        assert!(matches!(plugins.all_plugins[0].status, PluginStatus::Failed(_)), "{:?}", plugins.all_plugins[0].status);
+    }
+
+    fn statuses(plugins: &Plugins) -> Vec<(String, String)> {
+        plugins.all_plugins.iter().map(|entry| (entry.name.clone(), format!("{:?}", entry.status))).collect()
+    }
+
+    #[test]
+    fn plugins_with_an_invalid_manifest_are_listed_as_incompatible() {
+        let dir = test_dir("invalid-manifest");
+        fake_library(&dir, "broken");
+        std::fs::write(dir.join("broken.toml"), "name = \"Broken\"\nauthor = \"Me\"").unwrap();
+        fake_library(&dir, "future");
+        std::fs::write(dir.join("future.toml"), "name = \"Future\"\nmin_host_version = \"999.0.0\"").unwrap();
+        let mut plugins = plugins(&dir, LoadFilter::default());
+        plugins.load_all();
+
+        let [broken, future] = &plugins.all_plugins[..] else {
+            panic!("{:?}", statuses(&plugins))
+        };
+        assert_eq!(broken.name, "broken");
+        assert!(matches!(&broken.status, PluginStatus::Incompatible(reason) if reason.contains("unknown field `author`")), "{:?}", broken.status);
+        assert_eq!(broken.last_error.as_deref(), broken.status.reason());
+        assert_eq!(future.name, "Future");
+        assert!(matches!(&future.status, PluginStatus::Incompatible(reason) if reason.contains("requires xtea 999.0.0")), "{:?}", future.status);
+
+        // Fixed, and not enabled by default so the fake library isn't opened
+        std::fs::write(dir.join("broken.toml"), "name = \"Broken\"\ndefault_enabled = false").unwrap();
+        plugins.rescan();
+        let future = PluginStatus::Incompatible(format!("Future requires xtea 999.0.0 or newer, but this is xtea {}", manifest::host_version()));
+        assert_eq!(statuses(&plugins), [
+            ("Broken".to_string(), "Discovered".to_string()),
+            ("Future".to_string(), format!("{future:?}")),
+        ]);
+    }
+
+    #[test]
+    fn disabled_plugins_are_not_opened_and_unknown_ones_are_kept() {
+        let dir = test_dir("known");
+        let disabled = fake_library(&dir, "libdisabled");
+        let gone = PluginId::of_library("Gone", &dir.join("libgone.so"));
+        let disabled_id = PluginId::of_library("Disabled", &disabled);
+        let known = |name: &str, library: &Path| PluginConfig {
+            name: name.to_string(),
+            enabled: false,
+            library: library.to_path_buf(),
+            auto_reload: true,
+        };
+        let config = PluginsConfig {
+            ui_build_order: vec![gone.clone(), disabled_id.clone()],
+            known: BTreeMap::from([
+                (gone.clone(), known("Gone", &dir.join("libgone.so"))),
+                (disabled_id.clone(), known("Disabled", &disabled)),
+            ]),
+            ..PluginsConfig::default()
+        };
+        let mut plugins = Plugins::new(SearchPaths::with_dirs([(dir.clone(), SearchOrigin::CommandLine)]), config);
+        plugins.load_all();
+
+        assert_eq!(statuses(&plugins), [("Disabled".to_string(), "Disabled".to_string())]);
+        assert_eq!(plugins.all_plugins[0].id, disabled_id);
+        let config = plugins.config();
+        assert_eq!(config.known.keys().collect::<Vec<_>>(), [&disabled_id, &gone]);
+        assert_eq!(config.ui_build_order, std::slice::from_ref(&disabled_id));
+        assert_eq!(config.view_submenu_order, [disabled_id]);
+    }
+
+    #[test]
+    fn ids_come_from_the_manifest_or_the_library() {
+        let manifest = |id: Option<&str>| Manifest {
+            id: id.map(str::to_string),
+            ..toml::from_str("name = \"Counter\"").unwrap()
+        };
+        let library = Path::new("plugins/libcounter.so");
+        let with_id = manifest(Some("com.example.counter"));
+        assert_eq!(PluginId::new("Counter", Some(&with_id), library).to_string(), "com.example.counter");
+        assert_eq!(PluginId::new("Counter", Some(&manifest(None)), library).to_string(), "Counter@plugins/libcounter.so");
+        assert_eq!(PluginId::new("Counter", None, library), PluginId::of_library("Counter", library));
+        assert_ne!(PluginId::new("Counter", None, library), PluginId::new("Counter", None, Path::new("other/libcounter.so")));
+    }
+
+    #[test]
+    fn later_plugins_with_the_same_name_or_id_are_conflicts() {
+        let first = test_dir("conflict-first");
+        let second = test_dir("conflict-second");
+        // Not enabled by default, so the fake libraries aren't opened
+        let manifest = |dir: &Path, library: &str, name: &str, id: &str| {
+            fake_library(dir, library);
+            let manifest = format!("name = \"{name}\"\nid = \"{id}\"\ndefault_enabled = false");
+            std::fs::write(dir.join(format!("{library}.toml")), manifest).unwrap();
+        };
+        manifest(&first, "counter", "Counter", "com.example.counter");
+        manifest(&second, "counter", "Counter", "com.example.other");
+        manifest(&second, "renamed", "Renamed", "com.example.counter");
+        let mut plugins = Plugins::new(
+            SearchPaths::with_dirs([(first.clone(), SearchOrigin::User), (second.clone(), SearchOrigin::CommandLine)]),
+            PluginsConfig::default(),
+        );
+        plugins.load_all();
+
+        let [counter, same_name, same_id] = &plugins.all_plugins[..] else {
+            panic!("{:?}", statuses(&plugins))
+        };
+        assert!(matches!(counter.status, PluginStatus::Discovered));
+        assert_eq!(counter.id.to_string(), "com.example.counter");
+        let first_library = first.join(format!("counter.{}", std::env::consts::DLL_EXTENSION));
+        assert_eq!(
+            same_name.status.reason().unwrap(),
+            format!("a plugin called Counter was already found in {} (user data directory)", first_library.display()),
+        );
+        assert_eq!(
+            same_id.status.reason().unwrap(),
+            format!("a plugin with ID com.example.counter was already found in {} (user data directory)", first_library.display()),
+        );
+        // Every entry has its own ID, and conflicts aren't ordered
+        assert_eq!(same_name.id, PluginId::of_library("Counter", &same_name.path));
+        assert_eq!(same_id.id, PluginId::of_library("Renamed", &same_id.path));
+        assert_eq!(plugins.config().ui_build_order, std::slice::from_ref(&counter.id));
+    }
+
+    #[test]
+    fn load_filters_match_any_name() {
+        let filter = LoadFilter { only: Some(vec!["a".to_string(), "liba".to_string()]), disable: vec!["b".to_string()], safe_mode: false };
+        assert!(filter.allows(&["a"]));
+        assert!(filter.allows(&["A Plugin", "id", "liba"]));
+        assert!(!filter.allows(&["c"]));
+        assert!(!filter.allows(&["a", "b"]));
+        assert!(filter.excludes(&["b"]));
+        assert!(!filter.excludes(&["c"]));
+
+        let safe_mode = LoadFilter { safe_mode: true, ..LoadFilter::default() };
+        assert!(!safe_mode.allows(&["a"]));
+        assert!(safe_mode.excludes(&["a"]));
+        assert!(LoadFilter::default().allows(&["a"]));
+    }
+}
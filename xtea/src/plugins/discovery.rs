@@ -0,0 +1,292 @@
+use std::{path::{Path, PathBuf}, fmt::Display};
+
+use log::{error, trace, warn};
+
+/// Environment variable with extra plugin directories, separated like `PATH`.
+pub const PLUGIN_PATH_ENV: &str = "XTEA_PLUGIN_PATH";
+
+const PLUGIN_DIR_NAME: &str = "plugins";
+
+/// Where a plugin directory came from. Earlier origins take precedence.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchOrigin {
+    WorkingDir,
+    Executable,
+    User,
+    System,
+    Environment,
+    CommandLine,
+}
+
+impl Display for SearchOrigin {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let origin = match self {
+            SearchOrigin::WorkingDir => "working directory",
+            SearchOrigin::Executable => "executable directory",
+            SearchOrigin::User => "user data directory",
+            SearchOrigin::System => "system directory",
+            SearchOrigin::Environment => PLUGIN_PATH_ENV,
+            SearchOrigin::CommandLine => "command line",
+        };
+        write!(f, "{origin}")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SearchDir {
+    pub path: PathBuf,
+    pub origin: SearchOrigin,
+}
+
+/// A library file found in one of the search directories.
+#[derive(Debug, Clone)]
+pub struct Candidate {
+    pub path: PathBuf,
+    pub origin: SearchOrigin,
+    /// Whether the library lives in its own plugin subfolder.
+    pub in_subfolder: bool,
+}
+
+/// Ordered list of directories that are scanned for plugins.
+pub struct SearchPaths {
+    dirs: Vec<SearchDir>,
+}
+
+impl SearchPaths {
+    /// Builds the default search order: working directory, executable directory,
+    /// user data directory, system directory, `XTEA_PLUGIN_PATH` and finally `extra_dirs`.
+    pub fn new(extra_dirs: Vec<PathBuf>) -> SearchPaths {
+        let mut search_paths = SearchPaths { dirs: Vec::new() };
+
+        search_paths.push(PathBuf::from(PLUGIN_DIR_NAME), SearchOrigin::WorkingDir);
+        if let Some(exe_dir) = std::env::current_exe().ok().and_then(|exe| exe.parent().map(Path::to_path_buf)) {
+            search_paths.push(exe_dir.join(PLUGIN_DIR_NAME), SearchOrigin::Executable);
+        }
+        if let Some(data_dir) = dirs::data_dir() {
+            search_paths.push(data_dir.join("xtea").join(PLUGIN_DIR_NAME), SearchOrigin::User);
+        }
+        if let Some(system_dir) = system_plugin_dir() {
+            search_paths.push(system_dir, SearchOrigin::System);
+        }
+        if let Some(env_paths) = std::env::var_os(PLUGIN_PATH_ENV) {
+            for path in std::env::split_paths(&env_paths) {
+                search_paths.push(path, SearchOrigin::Environment);
+            }
+        }
+        for path in extra_dirs {
+            search_paths.push(path, SearchOrigin::CommandLine);
+        }
+
+        search_paths
+    }
+
+    /// Only `dirs`, in this order.
+    #[cfg(test)]
+    pub fn with_dirs(dirs: impl IntoIterator<Item = (PathBuf, SearchOrigin)>) -> SearchPaths {
+        let mut search_paths = SearchPaths { dirs: Vec::new() };
+        for (path, origin) in dirs {
+            search_paths.push(path, origin)
+        }
+
+        search_paths
+    }
+
+    fn push(&mut self, path: PathBuf, origin: SearchOrigin) {
+        if path.as_os_str().is_empty() {
+            return
+        }
+        let canonical = path.canonicalize().unwrap_or_else(|_| path.clone());
+        let already_listed = self.dirs.iter().any(|dir| {
+            dir.path.canonicalize().unwrap_or_else(|_| dir.path.clone()) == canonical
+        });
+        if !already_listed {
+            self.dirs.push(SearchDir { path, origin })
+        }
+    }
+
+    pub fn dirs(&self) -> &[SearchDir] {
+        &self.dirs
+    }
+
+    /// The user plugin directory, where plugins are installed.
+    pub fn user_dir(&self) -> Option<&Path> {
+        self.dirs.iter().find(|dir| dir.origin == SearchOrigin::User).map(|dir| dir.path.as_path())
+    }
+
+    /// Lists every plugin library in the search directories, in search order.
+    pub fn discover(&self) -> Vec<Candidate> {
+        let mut candidates = Vec::new();
+        for dir in self.dirs.iter() {
+            if !dir.path.is_dir() {
+                trace!("Plugin directory {} ({}) doesn't exist", dir.path.display(), dir.origin);
+                continue
+            }
+            trace!("Searching plugins in {} ({})", dir.path.display(), dir.origin);
+            for (path, in_subfolder) in scan_dir(&dir.path) {
+                candidates.push(Candidate { path, origin: dir.origin, in_subfolder })
+            }
+        }
+
+        candidates
+    }
+}
+
+/// Returns the libraries directly inside `dir`, plus the library of each plugin subfolder.
+fn scan_dir(dir: &Path) -> Vec<(PathBuf, bool)> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            error!("Could not read plugin directory {}\n\t{e}", dir.display());
+            return Vec::new()
+        },
+    };
+
+    let mut libraries = Vec::new();
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            let mut sub_libraries = match std::fs::read_dir(&path) {
+                Ok(entries) => entries
+                    .flatten()
+                    .map(|entry| entry.path())
+                    .filter(|path| is_library(path))
+                    .collect::<Vec<_>>(),
+                Err(e) => {
+                    error!("Could not read plugin directory {}\n\t{e}", path.display());
+                    continue
+                },
+            };
+            match sub_libraries.len() {
+                0 => (),
+                1 => libraries.push((sub_libraries.remove(0), true)),
+                _ => warn!(
+                    "Plugin folder {} contains more than one library, skipping it. Each subfolder must contain a single plugin",
+                    path.display()
+                ),
+            }
+        } else if is_library(&path) {
+            libraries.push((path, false))
+        }
+    }
+    libraries.sort();
+
+    libraries
+}
+
+/// Whether `path` has the shared library extension of the current platform (`.so`, `.dylib` or `.dll`).
+pub fn is_library(path: &Path) -> bool {
+    path.is_file() && path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION)
+}
+
+#[cfg(target_os = "linux")]
+fn system_plugin_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/usr/lib/xtea/plugins"))
+}
+
+#[cfg(target_os = "macos")]
+fn system_plugin_dir() -> Option<PathBuf> {
+    Some(PathBuf::from("/Library/Application Support/xtea/plugins"))
+}
+
+#[cfg(windows)]
+fn system_plugin_dir() -> Option<PathBuf> {
+    std::env::var_os("PROGRAMDATA").map(|program_data| PathBuf::from(program_data).join("xtea").join(PLUGIN_DIR_NAME))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "macos", windows)))]
+fn system_plugin_dir() -> Option<PathBuf> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-discovery-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn library(name: &str) -> String {
+        format!("{name}.{}", std::env::consts::DLL_EXTENSION)
+    }
+
+    fn touch(path: PathBuf) -> PathBuf {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        std::fs::write(&path, b"").unwrap();
+        path
+    }
+
+    #[test]
+    fn search_order_ends_with_the_command_line() {
+        let search_paths = SearchPaths::new(vec![PathBuf::from("extra/plugins")]);
+        let dirs = search_paths.dirs();
+        assert_eq!(dirs[0].path, Path::new(PLUGIN_DIR_NAME));
+        assert_eq!(dirs[0].origin, SearchOrigin::WorkingDir);
+        assert_eq!(dirs.last().unwrap().path, Path::new("extra/plugins"));
+        assert_eq!(dirs.last().unwrap().origin, SearchOrigin::CommandLine);
+
+        let origins = dirs.iter().map(|dir| dir.origin as usize).collect::<Vec<_>>();
+        assert!(origins.is_sorted(), "{dirs:?}");
+    }
+
+    #[test]
+    fn directories_are_listed_once() {
+        let dir = test_dir("dedup");
+        std::fs::create_dir_all(dir.join("sub")).unwrap();
+        let search_paths = SearchPaths::with_dirs([
+            (dir.clone(), SearchOrigin::User),
+            (PathBuf::new(), SearchOrigin::Environment),
+            (dir.join("sub").join(".."), SearchOrigin::Environment),
+            (dir.join("sub"), SearchOrigin::CommandLine),
+            (dir.clone(), SearchOrigin::CommandLine),
+        ]);
+
+        let dirs = search_paths.dirs().iter().map(|dir| (dir.path.clone(), dir.origin)).collect::<Vec<_>>();
+        assert_eq!(dirs, [(dir.clone(), SearchOrigin::User), (dir.join("sub"), SearchOrigin::CommandLine)]);
+        assert_eq!(search_paths.user_dir(), Some(dir.as_path()));
+    }
+
+    #[test]
+    fn libraries_and_plugin_folders_are_discovered_in_order() {
+        let first = test_dir("first");
+        let second = test_dir("second");
+        let b = touch(first.join(library("b")));
+        let a = touch(first.join(library("a")));
+        touch(first.join("a.toml"));
+        touch(first.join("readme.txt"));
+        let folder = touch(first.join("folder").join(library("plugin")));
+        touch(first.join("two").join(library("one")));
+        touch(first.join("two").join(library("other")));
+        std::fs::create_dir_all(first.join("empty")).unwrap();
+        let c = touch(second.join(library("c")));
+
+        let search_paths = SearchPaths::with_dirs([
+            (first.clone(), SearchOrigin::WorkingDir),
+            (first.join("missing"), SearchOrigin::User),
+            (second.clone(), SearchOrigin::CommandLine),
+        ]);
+        let candidates = search_paths.discover().into_iter()
+            .map(|candidate| (candidate.path, candidate.origin, candidate.in_subfolder))
+            .collect::<Vec<_>>();
+        assert_eq!(candidates, [
+            (a, SearchOrigin::WorkingDir, false),
+            (b, SearchOrigin::WorkingDir, false),
+            (folder, SearchOrigin::WorkingDir, true),
+            (c, SearchOrigin::CommandLine, false),
+        ]);
+    }
+
+    #[test]
+    fn libraries_have_the_platform_extension() {
+        let dir = test_dir("extension");
+        assert!(is_library(&touch(dir.join(library("plugin")))));
+        assert!(!is_library(&touch(dir.join("plugin.toml"))));
+        assert!(!is_library(&dir.join(library("missing"))));
+        std::fs::create_dir_all(dir.join(library("folder"))).unwrap();
+        assert!(!is_library(&dir.join(library("folder"))));
+    }
+}
@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+
+use super::{discovery::is_library, manifest::manifest_path};
+
+/// Copies a plugin into `plugin_dir`, and returns where its library ended up.
+///
+/// `source` is either a library, copied along with its manifest if it has one,
+/// or a plugin folder with a single library, copied whole.
+pub fn install(source: &Path, plugin_dir: &Path) -> Result<PathBuf, String> {
+    let Some(file_name) = source.file_name() else {
+        return Err(format!("{} is not a file or a folder", source.display()))
+    };
+    let destination = plugin_dir.join(file_name);
+    if destination.exists() {
+        return Err(format!("{} is already installed", destination.display()))
+    }
+    std::fs::create_dir_all(plugin_dir)
+        .map_err(|e| format!("Could not create {}: {e}", plugin_dir.display()))?;
+
+    if source.is_dir() {
+        let libraries = std::fs::read_dir(source)
+            .map_err(|e| format!("Could not read {}: {e}", source.display()))?
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| is_library(path))
+            .collect::<Vec<_>>();
+        let [library] = libraries.as_slice() else {
+            return Err(format!("A plugin folder must contain a single library, {} contains {}", source.display(), libraries.len()))
+        };
+        copy_dir(source, &destination)
+            .map_err(|e| format!("Could not copy {} to {}: {e}", source.display(), destination.display()))?;
+
+        Ok(destination.join(library.file_name().unwrap_or_default()))
+    } else if is_library(source) {
+        std::fs::copy(source, &destination)
+            .map_err(|e| format!("Could not copy {} to {}: {e}", source.display(), destination.display()))?;
+        if let Some(manifest) = manifest_path(source, false) {
+            let manifest_destination = destination.with_extension("toml");
+            std::fs::copy(&manifest, &manifest_destination)
+                .map_err(|e| format!("Could not copy {} to {}: {e}", manifest.display(), manifest_destination.display()))?;
+        }
+
+        Ok(destination)
+    } else {
+        Err(format!(
+            "{} is not a plugin library (.{}) or a plugin folder", source.display(), std::env::consts::DLL_EXTENSION
+        ))
+    }
+}
+
+/// Deletes a plugin's files: its whole folder if it has one, otherwise its library and manifest.
+pub fn uninstall(library: &Path, in_subfolder: bool) -> Result<(), String> {
+    if in_subfolder {
+        let Some(folder) = library.parent() else {
+            return Err(format!("{} has no plugin folder", library.display()))
+        };
+        return std::fs::remove_dir_all(folder).map_err(|e| format!("Could not remove {}: {e}", folder.display()))
+    }
+
+    if let Some(manifest) = manifest_path(library, false) {
+        std::fs::remove_file(&manifest).map_err(|e| format!("Could not remove {}: {e}", manifest.display()))?;
+    }
+    std::fs::remove_file(library).map_err(|e| format!("Could not remove {}: {e}", library.display()))
+}
+
+fn copy_dir(source: &Path, destination: &Path) -> std::io::Result<()> {
+    std::fs::create_dir_all(destination)?;
+    for entry in std::fs::read_dir(source)? {
+        let entry = entry?;
+        let path = entry.path();
+        if path.is_dir() {
+            copy_dir(&path, &destination.join(entry.file_name()))?
+        } else {
+            std::fs::copy(&path, destination.join(entry.file_name()))?;
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-install-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn library_name(name: &str) -> String {
+        format!("{name}.{}", std::env::consts::DLL_EXTENSION)
+    }
+
+    #[test]
+    fn libraries_are_installed_with_their_manifest() {
+        let source = test_dir("library-source");
+        let user_dir = test_dir("library-user").join("plugins");
+        std::fs::write(source.join(library_name("counter")), "library").unwrap();
+        std::fs::write(source.join("counter.toml"), "name = \"Counter\"").unwrap();
+
+        let library = install(&source.join(library_name("counter")), &user_dir).unwrap();
+        assert_eq!(library, user_dir.join(library_name("counter")));
+        assert_eq!(std::fs::read_to_string(&library).unwrap(), "library");
+        assert_eq!(std::fs::read_to_string(user_dir.join("counter.toml")).unwrap(), "name = \"Counter\"");
+
+        let error = install(&source.join(library_name("counter")), &user_dir).unwrap_err();
+        assert_eq!(error, format!("{} is already installed", library.display()));
+
+        uninstall(&library, false).unwrap();
+        assert_eq!(std::fs::read_dir(&user_dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn folders_are_installed_whole() {
+        let source = test_dir("folder-source").join("counter");
+        let user_dir = test_dir("folder-user");
+        std::fs::create_dir_all(source.join("assets")).unwrap();
+        std::fs::write(source.join(library_name("counter")), "library").unwrap();
+        std::fs::write(source.join("plugin.toml"), "name = \"Counter\"").unwrap();
+        std::fs::write(source.join("assets").join("icon.png"), "icon").unwrap();
+
+        let library = install(&source, &user_dir).unwrap();
+        assert_eq!(library, user_dir.join("counter").join(library_name("counter")));
+        assert!(user_dir.join("counter").join("plugin.toml").is_file());
+        assert_eq!(std::fs::read_to_string(user_dir.join("counter").join("assets").join("icon.png")).unwrap(), "icon");
+
+        uninstall(&library, true).unwrap();
+        assert!(!user_dir.join("counter").exists());
+        assert!(user_dir.is_dir());
+    }
+
+    #[test]
+    fn only_plugins_are_installed() {
+        let source = test_dir("invalid-source");
+        let user_dir = test_dir("invalid-user");
+        std::fs::write(source.join("notes.txt"), "").unwrap();
+        let error = install(&source.join("notes.txt"), &user_dir).unwrap_err();
+        let expected = format!("is not a plugin library (.{}) or a plugin folder", std::env::consts::DLL_EXTENSION);
+        assert!(error.ends_with(&expected), "{error}");
+
+        let folder = source.join("two-libraries");
+        std::fs::create_dir_all(&folder).unwrap();
+        std::fs::write(folder.join(library_name("a")), "").unwrap();
+        std::fs::write(folder.join(library_name("b")), "").unwrap();
+        let error = install(&folder, &user_dir).unwrap_err();
+        assert!(error.ends_with("contains 2"), "{error}");
+        assert!(!user_dir.join("two-libraries").exists());
+    }
+}
@@ -0,0 +1,270 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    mem::ManuallyDrop,
+    path::{Path, PathBuf},
+    ptr::NonNull,
+    sync::{atomic::{AtomicU64, Ordering}, Arc, PoisonError, RwLock},
+    time::{Duration, Instant},
+};
+
+use dlopen::raw::Library;
+use imgui::Ui;
+use log::{debug, error, Record};
+use xtealib::{
+    abi::ABI_SYMBOL,
+    ffi::{FfiSlice, FfiStr, UiHandle},
+    host,
+    logs::{LogFilter, LogRecord},
+    plugin::{FfiLogRecord, FfiRecord, HostApi, PluginInstance, PluginVTable, ByteSink, ENTRY_POINT_SYMBOL},
+};
+
+use super::shadow::ShadowCopy;
+
+/// The name of the plugin each [`HostApi::log_source`] was given to. Read for every record plugins log.
+static LOG_SOURCES: RwLock<BTreeMap<u64, Arc<str>>> = RwLock::new(BTreeMap::new());
+static NEXT_LOG_SOURCE: AtomicU64 = AtomicU64::new(1);
+
+/// A loaded plugin library and, once initialized, its instance.
+pub struct Plugin {
+    path: PathBuf,
+    vtable: PluginVTable,
+    instance: Option<NonNull<PluginInstance>>,
+    /// The plugin panicked, its instance can only be destroyed.
+    crashed: bool,
+    // Closed in `drop`, after the instance is destroyed and before the copy is removed
+    library: ManuallyDrop<Library>,
+    /// `None` once the library is leaked, a plugin that doesn't stop its tasks keeps its copy.
+    shadow_copy: Option<ShadowCopy>,
+    /// How long copying and opening the library took.
+    load_time: Duration,
+    /// Tells the plugin's log records apart from other plugins'.
+    log_source: u64,
+    /// How long the plugin has to stop its tasks when it's unloaded.
+    shutdown_timeout: Duration,
+}
+
+// The instance is only accessed through `&mut Plugin`, and `XteaPlugin` requires `Send`
+unsafe impl Send for Plugin {}
+
+impl Plugin {
+    /// Checks the plugin's ABI descriptor and, if it matches the host's, gets the plugin's vtable.
+    ///
+    /// The library is loaded from a copy, so `path` can be rebuilt while the plugin is loaded.
+    /// Unloading it waits up to `shutdown_timeout` for its tasks.
+    pub fn load(path: &Path, shutdown_timeout: Duration) -> Result<Plugin, LoadError> {
+        let started = Instant::now();
+        let shadow_copy = ShadowCopy::new(path).map_err(LoadError::Copy)?;
+        let library = Library::open(shadow_copy.path()).map_err(LoadError::Library)?;
+
+        let (vtable, differences) = unsafe {
+            host::read_plugin(library.symbol(ABI_SYMBOL).ok(), library.symbol(ENTRY_POINT_SYMBOL).ok())
+        }.map_err(LoadError::Incompatible)?;
+        for difference in differences {
+            debug!("Plugin {}: {difference}", path.display())
+        }
+
+        Ok(Plugin {
+            path: path.to_path_buf(),
+            vtable,
+            instance: None,
+            crashed: false,
+            library: ManuallyDrop::new(library),
+            shadow_copy: Some(shadow_copy),
+            load_time: started.elapsed(),
+            log_source: NEXT_LOG_SOURCE.fetch_add(1, Ordering::Relaxed),
+            shutdown_timeout,
+        })
+    }
+
+    /// The optional functions this plugin provides.
+    pub fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            build_ui: self.vtable.build_ui.is_some(),
+            view_submenu: self.vtable.view_submenu.is_some(),
+            shutdown: self.vtable.shutdown.is_some(),
+            state: self.vtable.save_state.is_some() && self.vtable.restore_state.is_some(),
+        }
+    }
+
+    /// The library this plugin was loaded from.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn load_time(&self) -> Duration {
+        self.load_time
+    }
+
+    pub fn get_name(&self) -> String {
+        unsafe { (self.vtable.get_name)().as_str() }.to_string()
+    }
+
+    /// Shares the host's imgui context, allocator and logger with the plugin.
+    pub fn connect_host(&self) {
+        let name = self.get_name();
+        let filter = crate::IMGUI_LOGGER.filters().get(Some(&name)).clone();
+        let host = HostApi::new(filter.max_level(), host_log, self.log_source, host_log_record);
+        LOG_SOURCES.write().unwrap_or_else(PoisonError::into_inner).insert(self.log_source, name.into());
+        (self.vtable.connect_host)(&host);
+        self.set_log_filter(&filter)
+    }
+
+    /// Filters the log records in the plugin, before they're sent. Plugins built with an xtealib
+    /// without `set_log_filter` only get the filter's max level, when they connect.
+    pub fn set_log_filter(&self, filter: &LogFilter) {
+        if let Some(set_log_filter) = self.vtable.set_log_filter {
+            set_log_filter(FfiStr::new(&filter.to_string()))
+        }
+    }
+
+    /// Creates the plugin instance, or returns why the plugin couldn't create it.
+    pub fn init_plugin(&mut self) -> Result<(), String> {
+        let mut error = Vec::new();
+        let instance = match self.vtable.try_create {
+            Some(try_create) => try_create(&mut ByteSink::for_vec(&mut error)),
+            None => (self.vtable.create)(),
+        };
+        self.instance = NonNull::new(instance);
+
+        match (self.instance, error.is_empty()) {
+            (Some(_), _) => Ok(()),
+            (None, false) => Err(String::from_utf8_lossy(&error).into_owned()),
+            (None, true) => Err("it didn't create its instance".to_string()),
+        }
+    }
+
+    /// The instance's state, to restore it in the instance of the reloaded library.
+    pub fn save_state(&self) -> Option<Vec<u8>> {
+        let (instance, save_state) = (self.instance?, self.vtable.save_state?);
+        let mut state = Vec::new();
+        save_state(instance.as_ptr(), &mut ByteSink::for_vec(&mut state));
+
+        (!state.is_empty()).then_some(state)
+    }
+
+    pub fn restore_state(&mut self, state: &[u8]) {
+        if let (Some(instance), Some(restore_state)) = (self.instance, self.vtable.restore_state) {
+            restore_state(instance.as_ptr(), FfiSlice::new(state))
+        }
+    }
+
+    /// The report of the panic caught in the plugin since the last call, if any.
+    /// Must be checked after every call into the plugin.
+    pub fn take_panic(&mut self) -> Option<String> {
+        let take_panic = self.vtable.take_panic?;
+        let mut report = Vec::new();
+        take_panic(&mut ByteSink::for_vec(&mut report));
+        if report.is_empty() {
+            return None
+        }
+
+        self.crashed = true;
+        Some(String::from_utf8_lossy(&report).into_owned())
+    }
+
+    /// Offers a file from the command line to the plugin. Returns whether the plugin opened it.
+    pub fn open_file(&mut self, path: &Path) -> bool {
+        let (Some(instance), Some(open_file)) = (self.instance, self.vtable.open_file) else {
+            return false
+        };
+        let path = path.to_string_lossy();
+        open_file(instance.as_ptr(), FfiStr::new(&path))
+    }
+
+    pub fn build_ui(&mut self, ui: &Ui) {
+        if let (Some(instance), Some(build_ui)) = (self.instance, self.vtable.build_ui) {
+            build_ui(instance.as_ptr(), UiHandle::new(ui))
+        }
+    }
+
+    pub fn view_submenu(&mut self, ui: &Ui) {
+        if let (Some(instance), Some(view_submenu)) = (self.instance, self.vtable.view_submenu) {
+            view_submenu(instance.as_ptr(), UiHandle::new(ui))
+        }
+    }
+}
+
+impl Drop for Plugin {
+    fn drop(&mut self) {
+        let running_tasks = host::shutdown(&self.vtable, self.instance.take(), self.crashed, self.shutdown_timeout);
+        if running_tasks == 0 {
+            // The copy can't be removed while the library is open on windows
+            unsafe { ManuallyDrop::drop(&mut self.library) }
+            LOG_SOURCES.write().unwrap_or_else(PoisonError::into_inner).remove(&self.log_source);
+        } else {
+            error!(
+                "Plugin {} still has {running_tasks} tasks running after {:?}, its library will stay loaded",
+                self.path.display(), self.shutdown_timeout
+            );
+            std::mem::forget(self.shadow_copy.take())
+        }
+    }
+}
+
+/// Which optional functions a plugin provides.
+#[derive(Debug, Clone, Copy)]
+pub struct Capabilities {
+    pub build_ui: bool,
+    pub view_submenu: bool,
+    pub shutdown: bool,
+    /// Keeps its state across reloads.
+    pub state: bool,
+}
+
+impl Capabilities {
+    /// Human readable names of the provided capabilities.
+    pub fn names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.build_ui {
+            names.push("UI")
+        }
+        if self.view_submenu {
+            names.push("View menu")
+        }
+        if self.shutdown {
+            names.push("Shutdown")
+        }
+        if self.state {
+            names.push("State")
+        }
+
+        names
+    }
+}
+
+pub enum LoadError {
+    /// The plugin was built against an incompatible plugin API or Dear ImGui.
+    Incompatible(String),
+    /// The library couldn't be copied before loading it.
+    Copy(std::io::Error),
+    Library(dlopen::Error),
+}
+
+impl Display for LoadError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoadError::Incompatible(reason) => write!(f, "{reason}"),
+            LoadError::Copy(e) => write!(f, "could not copy the library: {e}"),
+            LoadError::Library(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+/// Receives the log records of plugins built with a recent xtealib.
+extern "C" fn host_log_record(record: &FfiLogRecord) {
+    let plugin = LOG_SOURCES.read().unwrap_or_else(PoisonError::into_inner).get(&record.source).cloned();
+    crate::IMGUI_LOGGER.push(unsafe { LogRecord::from_ffi(record, plugin) })
+}
+
+/// Receives the log records of plugins built with an xtealib without `log_record`.
+extern "C" fn host_log(record: &FfiRecord) {
+    let target = unsafe { record.target.as_str() };
+    let message = unsafe { record.message.as_str() };
+    log::logger().log(&Record::builder()
+        .level(record.level())
+        .target(target)
+        .args(format_args!("{message}"))
+        .build()
+    )
+}
@@ -0,0 +1,180 @@
+use std::path::{Path, PathBuf};
+
+use semver::Version;
+use serde::Deserialize;
+
+/// Name of the manifest inside a plugin subfolder.
+pub const MANIFEST_FILE_NAME: &str = "plugin.toml";
+
+/// Optional metadata that describes a plugin without having to load its library.
+///
+/// It is read from `plugin.toml` when the plugin lives in its own subfolder,
+/// or from a `.toml` file with the same name as the library otherwise.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub name: String,
+    /// Identifies the plugin even if its library moves. Must be unique, like a reverse domain name.
+    pub id: Option<String>,
+    pub version: Option<Version>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    pub description: Option<String>,
+    pub license: Option<String>,
+    pub homepage: Option<String>,
+    pub min_host_version: Option<Version>,
+    pub max_host_version: Option<Version>,
+    #[serde(default = "default_enabled")]
+    pub default_enabled: bool,
+}
+
+fn default_enabled() -> bool {
+    true
+}
+
+impl Manifest {
+    /// Reads the manifest that belongs to `library`, if there is one.
+    pub fn for_library(library: &Path, in_subfolder: bool) -> Result<Option<Manifest>, String> {
+        let Some(path) = manifest_path(library, in_subfolder) else {
+            return Ok(None)
+        };
+        let content = std::fs::read_to_string(&path)
+            .map_err(|e| format!("Could not read {}: {e}", path.display()))?;
+        toml::from_str(&content)
+            .map(Some)
+            .map_err(|e| format!("Invalid manifest {}: {e}", path.display()))
+    }
+
+    /// Checks the host version bounds, returns the reason if this plugin can't run on this xtea.
+    pub fn check_compatibility(&self) -> Result<(), String> {
+        let host_version = host_version();
+        if let Some(min_version) = &self.min_host_version {
+            if &host_version < min_version {
+                return Err(format!("{} requires xtea {min_version} or newer, but this is xtea {host_version}", self.name))
+            }
+        }
+        if let Some(max_version) = &self.max_host_version {
+            if &host_version > max_version {
+                return Err(format!("{} requires xtea {max_version} or older, but this is xtea {host_version}", self.name))
+            }
+        }
+
+        Ok(())
+    }
+}
+
+pub fn host_version() -> Version {
+    Version::parse(env!("CARGO_PKG_VERSION")).expect("xtea version is valid semver")
+}
+
+pub(super) fn manifest_path(library: &Path, in_subfolder: bool) -> Option<PathBuf> {
+    let manifest = if in_subfolder {
+        library.with_file_name(MANIFEST_FILE_NAME)
+    } else {
+        library.with_extension("toml")
+    };
+
+    manifest.is_file().then_some(manifest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An empty directory for one test.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("xtea-manifest-test-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn parse(manifest: &str) -> Result<Manifest, toml::de::Error> {
+        toml::from_str(manifest)
+    }
+
+    fn with_bounds(min: Option<&str>, max: Option<&str>) -> Manifest {
+        Manifest {
+            min_host_version: min.map(|min| min.parse().unwrap()),
+            max_host_version: max.map(|max| max.parse().unwrap()),
+            ..parse("name = \"Bounded\"").unwrap()
+        }
+    }
+
+    #[test]
+    fn only_the_name_is_required() {
+        let manifest = parse("name = \"My Plugin\"").unwrap();
+        assert_eq!(manifest.name, "My Plugin");
+        assert_eq!(manifest.id, None);
+        assert!(manifest.authors.is_empty());
+        assert!(manifest.default_enabled);
+
+        assert!(parse("id = \"com.example.my_plugin\"").is_err());
+    }
+
+    #[test]
+    fn every_field_is_read() {
+        let manifest = parse(r#"
+            name = "My Plugin"
+            id = "com.example.my_plugin"
+            version = "1.2.0"
+            authors = ["Me <me@example.com>"]
+            description = "What my plugin does"
+            license = "MIT"
+            homepage = "https://example.com/my_plugin"
+            min_host_version = "0.1.0"
+            max_host_version = "0.2.0"
+            default_enabled = false
+        "#).unwrap();
+        assert_eq!(manifest.id.as_deref(), Some("com.example.my_plugin"));
+        assert_eq!(manifest.version, Some(Version::new(1, 2, 0)));
+        assert_eq!(manifest.authors, ["Me <me@example.com>"]);
+        assert_eq!(manifest.min_host_version, Some(Version::new(0, 1, 0)));
+        assert_eq!(manifest.max_host_version, Some(Version::new(0, 2, 0)));
+        assert!(!manifest.default_enabled);
+    }
+
+    #[test]
+    fn unknown_fields_and_invalid_versions_are_rejected() {
+        let error = parse("name = \"My Plugin\"\nauthor = \"Me\"").unwrap_err();
+        assert!(error.to_string().contains("unknown field `author`"), "{error}");
+        assert!(parse("name = \"My Plugin\"\nversion = \"1.2\"").is_err());
+        assert!(parse("name = 1").is_err());
+    }
+
+    #[test]
+    fn host_version_bounds_are_inclusive() {
+        let host = host_version();
+        let next = Version::new(host.major, host.minor, host.patch + 1).to_string();
+        let host = host.to_string();
+        assert!(with_bounds(None, None).check_compatibility().is_ok());
+        assert!(with_bounds(Some(&host), Some(&host)).check_compatibility().is_ok());
+        assert!(with_bounds(Some("0.0.0"), Some(&next)).check_compatibility().is_ok());
+
+        assert_eq!(
+            with_bounds(Some(&next), None).check_compatibility(),
+            Err(format!("Bounded requires xtea {next} or newer, but this is xtea {host}")),
+        );
+        assert_eq!(
+            with_bounds(None, Some("0.0.0")).check_compatibility(),
+            Err(format!("Bounded requires xtea 0.0.0 or older, but this is xtea {host}")),
+        );
+    }
+
+    #[test]
+    fn manifests_are_next_to_the_library_or_in_its_folder() {
+        let dir = test_dir("paths");
+        let library = dir.join(format!("my_plugin.{}", std::env::consts::DLL_EXTENSION));
+        assert_eq!(manifest_path(&library, false), None);
+        assert!(Manifest::for_library(&library, false).unwrap().is_none());
+
+        std::fs::write(dir.join("my_plugin.toml"), "name = \"Beside\"").unwrap();
+        std::fs::write(dir.join(MANIFEST_FILE_NAME), "name = \"In its folder\"").unwrap();
+        assert_eq!(Manifest::for_library(&library, false).unwrap().unwrap().name, "Beside");
+        assert_eq!(Manifest::for_library(&library, true).unwrap().unwrap().name, "In its folder");
+
+        std::fs::write(dir.join("my_plugin.toml"), "name = ").unwrap();
+        let error = Manifest::for_library(&library, false).unwrap_err();
+        assert!(error.starts_with(&format!("Invalid manifest {}", dir.join("my_plugin.toml").display())), "{error}");
+    }
+}
@@ -0,0 +1,165 @@
+use std::{
+    collections::BTreeSet,
+    fs::{File, OpenOptions, TryLockError},
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicUsize, Ordering}, Mutex, PoisonError},
+};
+
+use log::{trace, warn};
+
+const SHADOW_DIR_PREFIX: &str = "xtea-plugins-";
+const LOCK_EXTENSION: &str = "lock";
+
+static NEXT_COPY_ID: AtomicUsize = AtomicUsize::new(0);
+/// Locked while this process has plugin copies, so other xtea processes leave them alone.
+static SHADOW_DIR_LOCK: Mutex<Option<File>> = Mutex::new(None);
+
+/// A private copy of a plugin library, so the original can be rebuilt while the plugin is loaded,
+/// and every reload opens a path the dynamic loader has never seen. Removed when dropped.
+pub struct ShadowCopy {
+    path: PathBuf,
+}
+
+impl ShadowCopy {
+    pub fn new(library: &Path) -> std::io::Result<ShadowCopy> {
+        let shadow_dir = shadow_dir();
+        lock_shadow_dir()?;
+        std::fs::create_dir_all(&shadow_dir)?;
+
+        let stem = library.file_stem().map(|stem| stem.to_string_lossy().into_owned()).unwrap_or_default();
+        let id = NEXT_COPY_ID.fetch_add(1, Ordering::Relaxed);
+        let path = shadow_dir.join(format!("{stem}-{id}.{}", std::env::consts::DLL_EXTENSION));
+        std::fs::copy(library, &path)?;
+        trace!("Copied plugin {} to {}", library.display(), path.display());
+
+        Ok(ShadowCopy { path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for ShadowCopy {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Could not remove plugin copy {}\n\t{e}", self.path.display())
+        }
+    }
+}
+
+/// Where this process puts its plugin copies.
+fn shadow_dir() -> PathBuf {
+    std::env::temp_dir().join(format!("{SHADOW_DIR_PREFIX}{}", std::process::id()))
+}
+
+/// The lock file of a shadow dir, next to it.
+fn lock_path(shadow_dir: &Path) -> PathBuf {
+    shadow_dir.with_extension(LOCK_EXTENSION)
+}
+
+/// Locks the shadow dir of this process until it's removed. Taken before the dir is created,
+/// so other processes never see it unlocked.
+fn lock_shadow_dir() -> std::io::Result<()> {
+    let mut lock = SHADOW_DIR_LOCK.lock().unwrap_or_else(PoisonError::into_inner);
+    if lock.is_none() {
+        let file = OpenOptions::new().create(true).truncate(false).write(true).open(lock_path(&shadow_dir()))?;
+        file.lock()?;
+        *lock = Some(file)
+    }
+
+    Ok(())
+}
+
+/// Removes the plugin copies of this process. The plugins must already be unloaded.
+pub fn remove_shadow_dir() {
+    let shadow_dir = shadow_dir();
+    if shadow_dir.exists() {
+        if let Err(e) = std::fs::remove_dir_all(&shadow_dir) {
+            warn!("Could not remove plugin copies in {}\n\t{e}", shadow_dir.display())
+        }
+    }
+    // Closed first, windows doesn't remove open files
+    if SHADOW_DIR_LOCK.lock().unwrap_or_else(PoisonError::into_inner).take().is_some() {
+        let _ = std::fs::remove_file(lock_path(&shadow_dir));
+    }
+}
+
+/// Removes the plugin copies left behind by xtea processes that didn't exit cleanly.
+///
+/// Every running xtea holds the lock of its shadow dir, so only the dirs nobody holds are removed,
+/// even when a process id was reused.
+pub fn remove_stale_shadow_dirs() {
+    let Ok(entries) = std::fs::read_dir(std::env::temp_dir()) else {
+        return
+    };
+    let own_dir = shadow_dir();
+    // A dir, its lock file, or both, when the process exited between removing them
+    let shadow_dirs = entries.flatten()
+        .filter(|entry| entry.file_name().to_string_lossy().starts_with(SHADOW_DIR_PREFIX))
+        .map(|entry| entry.path().with_extension(""))
+        .filter(|path| path != &own_dir)
+        .collect::<BTreeSet<_>>();
+    for path in shadow_dirs {
+        remove_if_stale(&path)
+    }
+}
+
+fn remove_if_stale(shadow_dir: &Path) {
+    let lock_path = lock_path(shadow_dir);
+    let lock = OpenOptions::new().create(true).truncate(false).write(true).open(&lock_path);
+    let lock = match lock.map(|lock| lock.try_lock().map(|()| lock)) {
+        Ok(Ok(lock)) => lock,
+        Ok(Err(TryLockError::WouldBlock)) => {
+            trace!("Plugin copies in {} are in use", shadow_dir.display());
+            return
+        },
+        Ok(Err(TryLockError::Error(e))) | Err(e) => {
+            trace!("Could not lock {}\n\t{e}", lock_path.display());
+            return
+        },
+    };
+
+    if shadow_dir.is_dir() {
+        match std::fs::remove_dir_all(shadow_dir) {
+            Ok(()) => trace!("Removed stale plugin copies in {}", shadow_dir.display()),
+            Err(e) => {
+                trace!("Could not remove stale plugin copies in {}\n\t{e}", shadow_dir.display());
+                return
+            },
+        }
+    }
+    drop(lock);
+    let _ = std::fs::remove_file(&lock_path);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unlocked_shadow_dirs_are_stale() {
+        let shadow_dir = std::env::temp_dir().join(format!("xtea-shadow-test-{}", std::process::id()));
+        std::fs::create_dir_all(&shadow_dir).unwrap();
+        std::fs::write(shadow_dir.join("plugin-0.so"), b"").unwrap();
+
+        let lock = File::create(lock_path(&shadow_dir)).unwrap();
+        lock.lock().unwrap();
+        remove_if_stale(&shadow_dir);
+        assert!(shadow_dir.join("plugin-0.so").exists());
+
+        drop(lock);
+        remove_if_stale(&shadow_dir);
+        assert!(!shadow_dir.exists());
+        assert!(!lock_path(&shadow_dir).exists());
+    }
+
+    #[test]
+    fn lock_files_without_their_dir_are_removed() {
+        let shadow_dir = std::env::temp_dir().join(format!("xtea-shadow-test-lock-{}", std::process::id()));
+        File::create(lock_path(&shadow_dir)).unwrap();
+
+        remove_if_stale(&shadow_dir);
+        assert!(!lock_path(&shadow_dir).exists());
+    }
+}
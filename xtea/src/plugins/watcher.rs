@@ -0,0 +1,75 @@
+use std::{collections::HashMap, path::PathBuf, sync::mpsc::{channel, Receiver}, time::{Duration, Instant}};
+
+use log::{error, trace};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use super::discovery::{is_library, SearchDir};
+
+/// How long a library must stay untouched before it's considered completely written.
+const DEBOUNCE: Duration = Duration::from_millis(500);
+
+/// Watches the plugin directories for libraries that are rebuilt.
+pub struct PluginWatcher {
+    _watcher: RecommendedWatcher,
+    events: Receiver<notify::Result<Event>>,
+    /// Changed libraries and when they last changed.
+    pending: HashMap<PathBuf, Instant>,
+}
+
+impl PluginWatcher {
+    pub fn new(dirs: &[SearchDir]) -> Option<PluginWatcher> {
+        let (sender, events) = channel();
+        let mut watcher = match notify::recommended_watcher(sender) {
+            Ok(watcher) => watcher,
+            Err(e) => {
+                error!("Could not watch plugin directories, plugins won't be reloaded automatically\n\t{e}");
+                return None
+            },
+        };
+
+        for dir in dirs.iter().filter(|dir| dir.path.is_dir()) {
+            match watcher.watch(&dir.path, RecursiveMode::Recursive) {
+                Ok(()) => trace!("Watching plugin directory {}", dir.path.display()),
+                Err(e) => error!("Could not watch plugin directory {}\n\t{e}", dir.path.display()),
+            }
+        }
+
+        Some(PluginWatcher {
+            _watcher: watcher,
+            events,
+            pending: HashMap::new(),
+        })
+    }
+
+    /// Libraries that changed and haven't been touched for a while.
+    pub fn changed_libraries(&mut self) -> Vec<PathBuf> {
+        while let Ok(event) = self.events.try_recv() {
+            match event {
+                Ok(event) if matches!(event.kind, EventKind::Create(_) | EventKind::Modify(_)) => {
+                    let now = Instant::now();
+                    for path in event.paths {
+                        if path.extension().is_some_and(|extension| extension == std::env::consts::DLL_EXTENSION) {
+                            self.pending.insert(path, now);
+                        }
+                    }
+                },
+                Ok(_) => (),
+                Err(e) => error!("Error while watching plugin directories\n\t{e}"),
+            }
+        }
+
+        let mut changed = Vec::new();
+        self.pending.retain(|path, last_change| {
+            if last_change.elapsed() < DEBOUNCE {
+                return true
+            }
+            // Deleted, or replaced by something that isn't a library
+            if is_library(path) {
+                changed.push(path.clone())
+            }
+            false
+        });
+
+        changed
+    }
+}
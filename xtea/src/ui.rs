@@ -1,87 +1,482 @@
-use std::{collections::HashMap, path::{Path, PathBuf}};
+use std::{path::Path, time::Duration};
 
-use imgui::{Ui, StyleColor, StyleVar};
+use imgui::Ui;
 use log::error;
 
-use crate::{IMGUI_LOGGER, Plugins};
+use crate::{config::LogsConfig, logs::LogStore, plugins::{Initializing, Plugin, PluginEntry, PluginId, PluginOrder, PluginStatus, Plugins}};
 
-use self::style_editor::style_editor_window;
+use self::{log_window::{log_window, LogWindow}, style_editor::style_editor_window};
 
+mod log_window;
 mod style_editor;
 
 pub struct UiState {
-    logs: HashMap<String, Vec<String>>,
+    log_window: LogWindow,
     view_logs: bool,
     view_style_editor: bool,
     view_plugin_manager: bool,
+    plugin_manager: PluginManagerState,
+    /// The Logs tab to bring to the front.
+    show_logs: Option<String>,
+}
+
+/// Dialogs of the Plugin Manager.
+#[derive(Default)]
+struct PluginManagerState {
+    install_path: String,
+    install_error: Option<String>,
+    uninstall: Option<PluginId>,
+    uninstall_error: Option<String>,
+    filter: String,
+    /// The plugin shown in the details pane.
+    selected: Option<PluginId>,
 }
 
 impl UiState {
-    pub fn new() -> UiState {
+    pub fn new(logs_config: &LogsConfig) -> UiState {
         UiState {
-            logs: HashMap::new(),
+            log_window: LogWindow::new(logs_config),
             view_logs: true,
             view_style_editor: false,
             view_plugin_manager: false,
+            plugin_manager: PluginManagerState::default(),
+            show_logs: None,
         }
     }
+
+    /// Takes the last records and finishes writing the log files, before xtea exits.
+    pub fn flush_logs(&mut self) {
+        self.log_window.flush()
+    }
 }
 
 pub(crate) fn create_ui(ui: &Ui, state: &mut UiState, plugins: &mut Plugins) {
     ui.dockspace_over_main_viewport();
+    state.log_window.drain();
 
     create_menu(ui, state, plugins);
+    if state.view_plugin_manager {
+        plugin_manager(ui, state, plugins);
+    }
+    if state.show_logs.is_some() {
+        state.view_logs = true
+    }
     if state.view_logs {
-        logger_windw(ui, state);
+        log_window(ui, &mut state.log_window, state.show_logs.take(), plugins);
     }
     if state.view_style_editor {
-        style_editor(ui, state);
-    }
-    if state.view_plugin_manager {
-        plugin_manager(ui, state, plugins);
+        style_editor(ui);
     }
 
-    for plugin in plugins.ui_build_iter() {
-        plugin.build_ui(ui)
-    }
+    plugins.build_ui(ui);
 }
 
-fn style_editor(ui: &Ui, state: &mut UiState) {
+fn style_editor(ui: &Ui) {
     ui.window("Style").focus_on_appearing(false).build(|| {
         style_editor_window(ui)
     });
 }
 
 fn plugin_manager(ui: &Ui, state: &mut UiState, plugins: &mut Plugins) {
+    let UiState { log_window, plugin_manager: state, show_logs, .. } = state;
     ui.window("Plugin Manager").focus_on_appearing(false).build(|| {
         if ui.button("Reload All Plugins") {
             plugins.reload_all_plugins();
         }
+        ui.same_line();
+        if ui.button("Rescan") {
+            plugins.rescan();
+        }
+        if ui.is_item_hovered() {
+            ui.tooltip_text("Look for plugins that were added or removed")
+        }
+        ui.same_line();
+        let can_install = plugins.search_paths().user_dir().is_some();
+        let disabled_token = ui.begin_disabled(!can_install);
+        if ui.button("Install from file...") {
+            state.install_error = None;
+            ui.open_popup("Install plugin");
+        }
+        disabled_token.end();
+        if !can_install && ui.is_item_hovered_with_flags(imgui::ItemHoveredFlags::ALLOW_WHEN_DISABLED) {
+            ui.tooltip_text("There is no user plugin directory to install into")
+        }
+        install_dialog(ui, state, plugins);
+
+        ui.input_text("##filter", &mut state.filter).hint("Filter by name, ID or path").build();
+        ui.same_line();
+        let active_count = plugins.loaded_plugins.len();
+        ui.text_disabled(format!("{active_count} of {} plugins active", plugins.all_plugins.len()));
+
+        let mut action = None;
+        let filter = state.filter.to_lowercase();
+        let flags = imgui::TableFlags::RESIZABLE | imgui::TableFlags::ROW_BG | imgui::TableFlags::BORDERS_INNER_V
+            | imgui::TableFlags::SIZING_STRETCH_PROP;
+        if let Some(_table) = ui.begin_table_with_flags("plugins", 7, flags) {
+            for name in ["Name", "Version", "Library", "Status", "Load time", "Last error"] {
+                ui.table_setup_column(name)
+            }
+            ui.table_setup_column_with(imgui::TableColumnSetup {
+                flags: imgui::TableColumnFlags::WIDTH_FIXED,
+                ..imgui::TableColumnSetup::new("Actions")
+            });
+            ui.table_headers_row();
+
+            for entry in plugins.all_plugins.iter().filter(|entry| matches_filter(entry, &filter)) {
+                // Names aren't unique, IDs are
+                let _id = ui.push_id(entry.id.to_string());
+                let active = plugins.loaded_plugins.contains_key(&entry.id);
+                let busy = matches!(entry.status, PluginStatus::Loading | PluginStatus::Initializing);
+                let unavailable = matches!(entry.status, PluginStatus::Incompatible(_) | PluginStatus::Conflict(_));
+                ui.table_next_row();
+
+                ui.table_next_column();
+                let selected = state.selected.as_ref() == Some(&entry.id);
+                if ui.selectable_config(&entry.name).selected(selected).build() {
+                    state.selected = if selected { None } else { Some(entry.id.clone()) }
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!("{}\nClick for details", entry.id))
+                }
+
+                ui.table_next_column();
+                if let Some(version) = entry.manifest.as_ref().and_then(|manifest| manifest.version.as_ref()) {
+                    ui.text(version.to_string())
+                }
+
+                ui.table_next_column();
+                ui.text_disabled(entry.path.display().to_string());
+                if ui.is_item_hovered() {
+                    ui.tooltip_text(format!("{} ({})", entry.path.display(), entry.origin))
+                }
+
+                ui.table_next_column();
+                plugin_status(ui, &entry.status, plugins.initializing(&entry.id));
+
+                ui.table_next_column();
+                if let (Some(load_time), Some(init_time)) = (entry.load_time, entry.init_time) {
+                    ui.text(format_duration(load_time + init_time));
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text(format!(
+                            "Opening the library: {}\nCreating the instance: {}",
+                            format_duration(load_time), format_duration(init_time)
+                        ))
+                    }
+                }
 
-        // to avoid borrowing `plugins`
-        let mut tmp_vec = vec![];
-        std::mem::swap(&mut plugins.all_plugins, &mut tmp_vec);
+                ui.table_next_column();
+                if let Some(error) = &entry.last_error {
+                    ui.text_colored([1.0, 0.4, 0.4, 1.0], error.lines().next().unwrap_or_default());
+                    if ui.is_item_hovered() {
+                        ui.tooltip_text("Click the plugin's name for the whole error")
+                    }
+                }
 
-        let mut activating_plugins = Vec::new();
-        for (plugin_name, dll_path) in tmp_vec.iter() {
-            let active = plugins.loaded_plugins.get(plugin_name).is_some();
-            if ui.checkbox(plugin_name, &mut (active.clone())) {
+                ui.table_next_column();
+                let disabled_token = ui.begin_disabled(busy || unavailable);
                 if active {
-                    plugins.unload(plugin_name)
+                    if ui.small_button("Disable") {
+                        action = Some(PluginAction::Unload(entry.id.clone()))
+                    }
+                    ui.same_line();
+                    if ui.small_button("Reload") {
+                        action = Some(PluginAction::Reload(entry.id.clone()))
+                    }
                 } else {
-                    if let Some(plugin_thread) = plugins.activate(dll_path) {
-                        activating_plugins.push(plugin_thread)
+                    let label = match entry.status {
+                        PluginStatus::Crashed(_) => "Restart",
+                        PluginStatus::Failed(_) => "Retry",
+                        _ => "Enable",
+                    };
+                    if ui.small_button(label) {
+                        action = Some(PluginAction::Activate(entry.id.clone()))
                     }
                 }
+                disabled_token.end();
+                ui.same_line();
+                if ui.small_button("Folder") {
+                    open_folder(entry.path.parent().unwrap_or(&entry.path))
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Open the folder of this plugin")
+                }
+                ui.same_line();
+                let log_tab = plugin_log_tab(log_window.logs(), entry);
+                let disabled_token = ui.begin_disabled(log_tab.is_none());
+                if ui.small_button("Logs") {
+                    *show_logs = log_tab.map(str::to_string)
+                }
+                disabled_token.end();
+                if log_tab.is_none() && ui.is_item_hovered_with_flags(imgui::ItemHoveredFlags::ALLOW_WHEN_DISABLED) {
+                    ui.tooltip_text("This plugin didn't log anything yet")
+                }
+                ui.same_line();
+                let disabled_token = ui.begin_disabled(busy);
+                if ui.small_button("Uninstall") {
+                    action = Some(PluginAction::Uninstall(entry.id.clone()))
+                }
+                disabled_token.end();
+            }
+        }
+
+        if let Some(entry) = state.selected.as_ref().and_then(|id| plugins.entry(id)) {
+            ui.separator();
+            ui.text(&entry.name);
+            plugin_details(ui, entry, plugins.loaded_plugins.get(&entry.id));
+            if plugins.loaded_plugins.contains_key(&entry.id) {
+                let mut auto_reload = plugins.auto_reload(&entry.id);
+                if ui.checkbox("Auto reload", &mut auto_reload) {
+                    action = Some(PluginAction::SetAutoReload(entry.id.clone(), auto_reload))
+                }
+                if ui.is_item_hovered() {
+                    ui.tooltip_text("Reload this plugin when its library changes")
+                }
+            }
+        }
+
+        match action {
+            Some(PluginAction::Unload(id)) => plugins.unload(&id),
+            Some(PluginAction::Activate(id)) => plugins.activate(&id),
+            Some(PluginAction::Reload(id)) => plugins.reload_plugin(&id),
+            Some(PluginAction::SetAutoReload(id, auto_reload)) => plugins.set_auto_reload(&id, auto_reload),
+            Some(PluginAction::Uninstall(id)) => {
+                state.uninstall = Some(id);
+                state.uninstall_error = None;
+                ui.open_popup("Uninstall plugin");
+            },
+            None => (),
+        }
+        uninstall_dialog(ui, state, plugins);
+
+        if ui.collapsing_header("Build order", imgui::TreeNodeFlags::empty()) {
+            plugin_order_editor(ui, plugins, PluginOrder::UiBuild)
+        }
+        if ui.collapsing_header("View menu order", imgui::TreeNodeFlags::empty()) {
+            plugin_order_editor(ui, plugins, PluginOrder::ViewSubmenu)
+        }
+        if ui.collapsing_header("Crashed plugins", imgui::TreeNodeFlags::empty()) {
+            let mut restart_policy = plugins.restart_policy();
+            let mut changed = ui.checkbox("Restart automatically", &mut restart_policy.auto_restart);
+            let disabled_token = ui.begin_disabled(!restart_policy.auto_restart);
+            changed |= ui.input_scalar("Max restarts per plugin", &mut restart_policy.max_restarts).step(1).build();
+            disabled_token.end();
+            if changed {
+                plugins.set_restart_policy(restart_policy)
+            }
+        }
+        if ui.collapsing_header("Search paths", imgui::TreeNodeFlags::empty()) {
+            for dir in plugins.search_paths().dirs() {
+                ui.bullet_text(format!("{} ({})", dir.path.display(), dir.origin))
+            }
+        }
+    });
+}
+
+/// What was clicked in the Plugin Manager, done once the plugin list isn't borrowed anymore.
+enum PluginAction {
+    Unload(PluginId),
+    Activate(PluginId),
+    Reload(PluginId),
+    SetAutoReload(PluginId, bool),
+    Uninstall(PluginId),
+}
+
+/// Whether the plugin's name, ID or library contains `filter`, which is lowercase.
+fn matches_filter(entry: &PluginEntry, filter: &str) -> bool {
+    filter.is_empty()
+        || entry.name.to_lowercase().contains(filter)
+        || entry.id.to_string().to_lowercase().contains(filter)
+        || entry.path.to_string_lossy().to_lowercase().contains(filter)
+}
+
+fn format_duration(duration: Duration) -> String {
+    if duration < Duration::from_secs(1) {
+        format!("{} ms", duration.as_millis())
+    } else {
+        format!("{:.1} s", duration.as_secs_f32())
+    }
+}
+
+/// The Logs tab of a plugin: the target it logged to, or its crate's root if it logged to several.
+fn plugin_log_tab<'a>(logs: &'a LogStore, entry: &PluginEntry) -> Option<&'a str> {
+    logs.targets()
+        .filter(|(_, log)| log.plugin() == Some(entry.name.as_str()))
+        .map(|(target, _)| target)
+        .min_by_key(|target| target.len())
+}
+
+fn open_folder(folder: &Path) {
+    let opener = if cfg!(target_os = "windows") {
+        "explorer"
+    } else if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    if let Err(e) = std::process::Command::new(opener).arg(folder).spawn() {
+        error!("Could not open {}\n\t{e}", folder.display())
+    }
+}
+
+fn install_dialog(ui: &Ui, state: &mut PluginManagerState, plugins: &mut Plugins) {
+    ui.modal_popup_config("Install plugin").always_auto_resize(true).build(|| {
+        ui.text("Library or plugin folder to copy into the user plugin directory:");
+        ui.set_next_item_width(400.0);
+        ui.input_text("##path", &mut state.install_path).build();
+        if let Some(error) = &state.install_error {
+            ui.text_colored([1.0, 0.4, 0.4, 1.0], error)
+        }
+        let disabled_token = ui.begin_disabled(state.install_path.trim().is_empty());
+        if ui.button("Install") {
+            match plugins.install(Path::new(state.install_path.trim())) {
+                Ok(()) => {
+                    state.install_path.clear();
+                    state.install_error = None;
+                    ui.close_current_popup();
+                },
+                Err(e) => state.install_error = Some(e),
             }
         }
+        disabled_token.end();
+        ui.same_line();
+        if ui.button("Cancel") {
+            ui.close_current_popup();
+        }
+    });
+}
 
-        plugins.wait_init_plugins(activating_plugins);
-        std::mem::swap(&mut plugins.all_plugins, &mut tmp_vec);
+fn uninstall_dialog(ui: &Ui, state: &mut PluginManagerState, plugins: &mut Plugins) {
+    ui.modal_popup_config("Uninstall plugin").always_auto_resize(true).build(|| {
+        let Some(entry) = state.uninstall.as_ref().and_then(|id| plugins.entry(id)) else {
+            ui.close_current_popup();
+            return
+        };
+        let target = if entry.in_subfolder { entry.path.parent().unwrap_or(&entry.path) } else { &entry.path };
+        ui.text(format!("Uninstall {}?", entry.name));
+        ui.text(format!("This deletes {}", target.display()));
+        if let Some(error) = &state.uninstall_error {
+            ui.text_colored([1.0, 0.4, 0.4, 1.0], error)
+        }
+        if ui.button("Uninstall") {
+            let id = state.uninstall.clone().expect("checked above");
+            match plugins.uninstall(&id) {
+                Ok(()) => {
+                    state.uninstall = None;
+                    ui.close_current_popup();
+                },
+                Err(e) => state.uninstall_error = Some(e),
+            }
+        }
+        ui.same_line();
+        if ui.button("Cancel") {
+            state.uninstall = None;
+            ui.close_current_popup();
+        }
     });
 }
 
-fn create_menu(ui: &Ui, state: &mut UiState, plugins: &Plugins) {
+fn plugin_status(ui: &Ui, status: &PluginStatus, initializing: Option<&Initializing>) {
+    match (status, initializing) {
+        (PluginStatus::Initializing, Some(initializing)) => {
+            ui.text_disabled(format!("initializing... {:.1}s", initializing.elapsed().as_secs_f32()));
+            if initializing.timed_out() {
+                ui.same_line();
+                ui.text_colored([1.0, 0.4, 0.4, 1.0], "taking too long")
+            }
+        },
+        (PluginStatus::Failed(_) | PluginStatus::Incompatible(_) | PluginStatus::Crashed(_) | PluginStatus::Conflict(_), _) => {
+            ui.text_colored([1.0, 0.4, 0.4, 1.0], status.label());
+            if let (Some(reason), true) = (status.reason(), ui.is_item_hovered()) {
+                // Only the panic message, the backtrace is too long for a tooltip
+                let reason = match status {
+                    PluginStatus::Crashed(report) => format!("{}\nThe backtrace is in the logs", report.lines().next().unwrap_or_default()),
+                    _ => reason.to_string(),
+                };
+                ui.tooltip_text(reason)
+            }
+        },
+        (status, _) => ui.text_disabled(status.label()),
+    }
+}
+
+fn plugin_order_editor(ui: &Ui, plugins: &mut Plugins, order: PluginOrder) {
+    let _id = ui.push_id(format!("{order:?}"));
+    let mut move_down = None;
+    let ids = plugins.order(order);
+    for (index, id) in ids.iter().enumerate() {
+        let _id = ui.push_id_usize(index);
+        let disabled_token = ui.begin_disabled(index == 0);
+        if ui.arrow_button("up", imgui::Direction::Up) {
+            move_down = Some(index - 1)
+        }
+        disabled_token.end();
+        ui.same_line();
+        let disabled_token = ui.begin_disabled(index + 1 == ids.len());
+        if ui.arrow_button("down", imgui::Direction::Down) {
+            move_down = Some(index)
+        }
+        disabled_token.end();
+        ui.same_line();
+        let name = plugins.entry(id).map_or_else(|| id.to_string(), |entry| entry.name.clone());
+        if plugins.loaded_plugins.contains_key(id) {
+            ui.text(name)
+        } else {
+            ui.text_disabled(name)
+        }
+    }
+
+    if let Some(index) = move_down {
+        plugins.move_down(order, index)
+    }
+}
+
+fn plugin_details(ui: &Ui, entry: &PluginEntry, plugin: Option<&Plugin>) {
+    ui.text(format!("{} ({})", entry.path.display(), entry.origin));
+    ui.text_disabled(format!("ID: {}", entry.id));
+    if let Some(plugin) = plugin {
+        let capabilities = plugin.capabilities().names();
+        if capabilities.is_empty() {
+            ui.text("Provides: nothing")
+        } else {
+            ui.text(format!("Provides: {}", capabilities.join(", ")))
+        }
+    }
+    if let Some(manifest) = &entry.manifest {
+        ui.separator();
+        if let Some(version) = &manifest.version {
+            ui.text(format!("Version: {version}"))
+        }
+        if !manifest.authors.is_empty() {
+            ui.text(format!("Authors: {}", manifest.authors.join(", ")))
+        }
+        if let Some(description) = &manifest.description {
+            ui.text_wrapped(description)
+        }
+        if let Some(license) = &manifest.license {
+            ui.text(format!("License: {license}"))
+        }
+        if let Some(homepage) = &manifest.homepage {
+            ui.text(format!("Homepage: {homepage}"))
+        }
+        match (&manifest.min_host_version, &manifest.max_host_version) {
+            (Some(min), Some(max)) => ui.text(format!("Requires xtea {min} to {max}")),
+            (Some(min), None) => ui.text(format!("Requires xtea {min} or newer")),
+            (None, Some(max)) => ui.text(format!("Requires xtea {max} or older")),
+            (None, None) => (),
+        }
+    }
+    if let Some(error) = &entry.last_error {
+        ui.separator();
+        let label = if entry.status.reason().is_some() { "Error:" } else { "Last error:" };
+        ui.text_colored([1.0, 0.4, 0.4, 1.0], label);
+        // Crash reports have a whole backtrace
+        ui.child_window("last_error").size([0.0, 150.0]).horizontal_scrollbar(true).build(|| {
+            ui.text(error)
+        });
+    }
+}
+
+fn create_menu(ui: &Ui, state: &mut UiState, plugins: &mut Plugins) {
     ui.main_menu_bar(|| {
         if ui.menu_item("View") {
             ui.open_popup("view_menu_item");
@@ -92,62 +487,8 @@ fn create_menu(ui: &Ui, state: &mut UiState, plugins: &Plugins) {
             ui.checkbox("Plugin Manager", &mut state.view_plugin_manager);
             ui.text("Plugins");
             ui.separator();
-            for plugin in plugins.view_submenu_iter() {
-                plugin.view_submenu(ui)
-            }
+            plugins.view_submenu(ui);
         });
     });
 }
 
-fn logger_windw(ui: &Ui, state: &mut UiState) {
-    let messages = IMGUI_LOGGER.clear();
-    for (key, mut val) in messages {
-        match state.logs.get_mut(&key) {
-            Some(vec) => vec.append(&mut val),
-            None => {state.logs.insert(key, val);},
-        }
-    }
-    let mut save_all_logs = false;
-    ui.window("Logs").focus_on_appearing(false).build(|| {
-        if let Some(_) = ui.tab_bar("logs") {
-            for (tab, messages) in state.logs.iter() {
-                if let Some(_) = ui.tab_item(tab) {
-                    if ui.button("Save Log") {
-                        let content = messages.iter().fold(String::new(), |old, new| format!("{old}\n{new}"));
-                        let log_path = PathBuf::from(format!("logs/{tab}.txt"));
-                        if !log_path.parent().unwrap().exists() {
-                            // logs folder doesn't exist
-                            if let Err(e) = std::fs::create_dir("logs") {
-                                error!("Could not create logs directory\n\t{e}")
-                            }
-                        }
-                        if let Err(e) = std::fs::write(log_path, content) {
-                            error!("Could not save log {tab}.txt\n\t{e}")
-                        }
-                    }
-                    if ui.is_item_hovered() {
-                        ui.tooltip_text(format!("Saves this log to logs/{tab}.txt"))
-                    }
-                    if ui.button("Save All logs") {
-                        save_all_logs = true
-                    }
-                    if ui.is_item_hovered() {
-                        ui.tooltip_text(format!("Saves all the logs to logs/<name of plugin>.txt"))
-                    }
-                    for message in messages {
-                        ui.text(message)
-                    }
-                }
-            }
-        };
-    });
-
-    if save_all_logs {
-        for (tab, messages) in state.logs.iter() {
-            let content = messages.iter().fold(String::new(), |old, new| format!("{old}\n{new}"));
-            if let Err(e) = std::fs::write(format!("logs/{tab}.txt"), content) {
-                error!("Could not save log {tab}.txt\n\t{e}")
-            }
-        }
-    }
-}
@@ -0,0 +1,563 @@
+//! The Logs window: a tab per target and one with every target, filtered by level and text.
+
+use std::{collections::{BTreeMap, HashMap, VecDeque}, fmt::Write, path::PathBuf, time::{SystemTime, UNIX_EPOCH}};
+
+use imgui::{Key, ListClipper, StyleColor, Ui};
+use log::{error, info, Level, LevelFilter};
+use regex::{Regex, RegexBuilder};
+use xtealib::{LogFilter, LogFilters, LogRecord};
+
+use crate::{IMGUI_LOGGER, config::LogsConfig, logs::{self, LogFormat, LogSinks, LogStore, TargetLog}, plugins::Plugins};
+
+/// Where logs are saved, relative to the directory xtea is started in.
+const SAVE_DIR: &str = "logs";
+
+/// The label of the tab with every target, its ID can't be a target's.
+const ALL_TAB: &str = "All###all_targets";
+
+pub struct LogWindow {
+    logs: LogStore,
+    filters: LogFiltersEditor,
+    /// Which levels are shown, from `Error` to `Trace`.
+    levels: [bool; 5],
+    search: String,
+    regex: bool,
+    /// Why `search` isn't a valid regex.
+    search_error: Option<String>,
+    line_filter: LineFilter,
+    /// Changes with `line_filter`, so the views are made again.
+    filter_generation: u64,
+    /// By target, `None` for the tab with every target.
+    views: HashMap<Option<String>, LogView>,
+    timestamps: bool,
+    auto_scroll: bool,
+    /// Records that arrived after this aren't shown until the window is unpaused.
+    paused_since: Option<SystemTime>,
+}
+
+/// The log filters as typed in the Logs window, applied with Enter.
+struct LogFiltersEditor {
+    global: String,
+    /// By plugin name, empty when the plugin uses the global filter.
+    plugins: BTreeMap<String, String>,
+    error: Option<String>,
+}
+
+/// Which of the kept records are shown.
+struct LineFilter {
+    levels: [bool; 5],
+    search: Option<Regex>,
+}
+
+/// The lines of a tab.
+#[derive(Default)]
+struct LogView {
+    /// The indexes of the records shown, oldest first.
+    matches: VecDeque<u64>,
+    /// The index of the next record to check.
+    next: u64,
+    /// The log's and the filter's generations the matches were made with.
+    generations: (u64, u64),
+    /// The indexes where the selection starts and ends, in any order.
+    selection: Option<(u64, u64)>,
+}
+
+impl LogWindow {
+    pub fn new(config: &LogsConfig) -> LogWindow {
+        LogWindow {
+            logs: LogStore::new(config.capacity_per_target, config.capacity_all, LogSinks::new(config.sinks.clone())),
+            filters: LogFiltersEditor::new(&IMGUI_LOGGER.filters()),
+            levels: [true; 5],
+            search: String::new(),
+            regex: false,
+            search_error: None,
+            line_filter: LineFilter { levels: [true; 5], search: None },
+            filter_generation: 0,
+            views: HashMap::new(),
+            timestamps: false,
+            auto_scroll: true,
+            paused_since: None,
+        }
+    }
+
+    pub fn logs(&self) -> &LogStore {
+        &self.logs
+    }
+
+    /// Takes the new records, whether the window is shown or not.
+    pub fn drain(&mut self) {
+        self.logs.drain(&IMGUI_LOGGER)
+    }
+
+    /// Takes every record left, and finishes writing the log files.
+    pub fn flush(&mut self) {
+        self.logs.flush(&IMGUI_LOGGER)
+    }
+
+    /// Makes the line filter again after the levels or the search changed.
+    fn update_line_filter(&mut self) {
+        let search = if self.regex { RegexBuilder::new(&self.search) } else { RegexBuilder::new(&regex::escape(&self.search)) }
+            .case_insensitive(!self.regex)
+            .build();
+        self.search_error = search.as_ref().err().map(ToString::to_string);
+        self.line_filter = LineFilter {
+            levels: self.levels,
+            // Everything is shown while the regex is invalid
+            search: search.ok().filter(|_| !self.search.is_empty()),
+        };
+        self.filter_generation += 1;
+    }
+}
+
+impl LogFiltersEditor {
+    fn new(filters: &LogFilters) -> LogFiltersEditor {
+        LogFiltersEditor {
+            global: filters.global.to_string(),
+            plugins: filters.plugins.iter().map(|(plugin, filter)| (plugin.clone(), filter.to_string())).collect(),
+            error: None,
+        }
+    }
+
+    fn parse(&self) -> Result<LogFilters, String> {
+        let global = self.global.parse::<LogFilter>()?;
+        let mut plugins = BTreeMap::new();
+        for (plugin, filter) in self.plugins.iter().filter(|(_, filter)| !filter.trim().is_empty()) {
+            plugins.insert(plugin.clone(), filter.parse().map_err(|e| format!("{plugin}: {e}"))?);
+        }
+
+        Ok(LogFilters { global, plugins })
+    }
+}
+
+impl LineFilter {
+    fn matches(&self, record: &LogRecord) -> bool {
+        self.levels[record.level as usize - 1] && self.search.as_ref().is_none_or(|search| {
+            search.is_match(&record.message)
+                || search.is_match(&record.target)
+                || record.fields.iter().any(|(key, value)| search.is_match(key) || search.is_match(value))
+        })
+    }
+}
+
+impl LogView {
+    /// Checks the records that arrived since the last update, or all of them if the log or the filter changed.
+    fn update(&mut self, log: &TargetLog, filter: &LineFilter, filter_generation: u64, paused_since: Option<SystemTime>) {
+        let range = log.range();
+        let generations = (log.generation(), filter_generation);
+        if generations != self.generations {
+            // The indexes of the selection may now be other records'
+            if generations.0 != self.generations.0 {
+                self.selection = None
+            }
+            self.matches.clear();
+            self.next = range.start;
+            self.generations = generations;
+        }
+        while self.matches.front().is_some_and(|&index| index < range.start) {
+            self.matches.pop_front();
+        }
+
+        for index in self.next.max(range.start)..range.end {
+            let Some(record) = log.get(index) else {
+                break
+            };
+            if paused_since.is_some_and(|since| record.timestamp > since) {
+                break
+            }
+            if filter.matches(record) {
+                self.matches.push_back(index)
+            }
+            self.next = index + 1;
+        }
+    }
+
+    fn is_selected(&self, index: u64) -> bool {
+        self.selection.is_some_and(|(start, end)| (start.min(end)..=start.max(end)).contains(&index))
+    }
+
+    /// Selects a line, or extends the selection up to it.
+    fn select(&mut self, index: u64, extend: bool) {
+        self.selection = match self.selection {
+            Some((start, _)) if extend => Some((start, index)),
+            _ => Some((index, index)),
+        }
+    }
+
+    /// The selected lines that are shown, in full.
+    fn selected_text(&self, log: &TargetLog, with_target: bool, timestamps: bool) -> String {
+        self.matches.iter()
+            .filter(|&&index| self.is_selected(index))
+            .filter_map(|&index| log.get(index))
+            .map(|record| line(record, with_target, timestamps, true))
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+pub fn log_window(ui: &Ui, window: &mut LogWindow, show_logs: Option<String>, plugins: &mut Plugins) {
+    let mut save_all_logs = None;
+    let mut clear = None;
+    ui.window("Logs").size([800.0, 400.0], imgui::Condition::FirstUseEver).focus_on_appearing(false).focused(show_logs.is_some()).build(|| {
+        let dropped = IMGUI_LOGGER.dropped();
+        if dropped > 0 {
+            ui.text_colored(level_color(Level::Warn), format!("{dropped} messages were dropped, they came faster than xtea could show them"))
+        }
+        let dropped = window.logs.sinks().dropped();
+        if dropped > 0 {
+            ui.text_colored(level_color(Level::Warn), format!("{dropped} messages weren't written to the log files, they came faster than they could be written"))
+        }
+        if ui.collapsing_header("Filters", imgui::TreeNodeFlags::empty()) {
+            log_filters_editor(ui, &mut window.filters, plugins)
+        }
+        toolbar(ui, window);
+
+        if let Some(_tab_bar) = ui.tab_bar("logs") {
+            if let Some(_tab_item) = ui.tab_item(ALL_TAB) {
+                clear = log_tab(ui, window, None, plugins, &mut save_all_logs);
+            }
+            let targets = window.logs.targets().map(|(target, _)| target.to_string()).collect::<Vec<_>>();
+            for target in targets {
+                let flags = if show_logs.as_ref() == Some(&target) { imgui::TabItemFlags::SET_SELECTED } else { imgui::TabItemFlags::empty() };
+                if let Some(_tab_item) = ui.tab_item_with_flags(&target, None, flags) {
+                    clear = log_tab(ui, window, Some(&target), plugins, &mut save_all_logs);
+                }
+            }
+        }
+    });
+
+    if let Some(target) = clear {
+        window.logs.clear(target.as_deref())
+    }
+    if let Some(format) = save_all_logs {
+        for (target, log) in window.logs.targets() {
+            save_log(target, log, format)
+        }
+    }
+}
+
+/// The levels, the search and the display options, for every tab.
+fn toolbar(ui: &Ui, window: &mut LogWindow) {
+    let mut changed = false;
+    for level in Level::iter() {
+        let _color = ui.push_style_color(StyleColor::Text, level_color(level));
+        changed |= ui.checkbox(level.as_str(), &mut window.levels[level as usize - 1]);
+        ui.same_line();
+    }
+    ui.set_next_item_width(200.0);
+    changed |= ui.input_text("##search", &mut window.search).hint("Search").build();
+    ui.same_line();
+    changed |= ui.checkbox("Regex", &mut window.regex);
+    if changed {
+        window.update_line_filter()
+    }
+
+    ui.same_line();
+    ui.checkbox("Timestamps", &mut window.timestamps);
+    if ui.is_item_hovered() {
+        ui.tooltip_text("When xtea received each message, in UTC")
+    }
+    ui.same_line();
+    ui.checkbox("Auto-scroll", &mut window.auto_scroll);
+    ui.same_line();
+    let mut paused = window.paused_since.is_some();
+    if ui.checkbox("Pause", &mut paused) {
+        window.paused_since = paused.then(SystemTime::now)
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text("Stop showing new messages, they're still kept")
+    }
+    if let Some(error) = &window.search_error {
+        ui.text_colored(level_color(Level::Error), error)
+    }
+}
+
+/// The lines of `target`, or of every target. Returns the tab to clear, if it should be.
+fn log_tab(ui: &Ui, window: &mut LogWindow, target: Option<&str>, plugins: &mut Plugins, save_all_logs: &mut Option<LogFormat>) -> Option<Option<String>> {
+    let LogWindow { logs, filters, line_filter, filter_generation, views, timestamps, auto_scroll, paused_since, .. } = window;
+    let log = match target {
+        Some(target) => logs.target(target)?,
+        None => logs.all(),
+    };
+    let view = views.entry(target.map(str::to_string)).or_default();
+    view.update(log, line_filter, *filter_generation, *paused_since);
+
+    let mut clear = None;
+    let name = target.unwrap_or("all");
+    if let Some(format) = format_menu(ui, "Save Log", &format!("Saves this log to {SAVE_DIR}/{}", logs::file_name(name))) {
+        save_log(name, log, format)
+    }
+    ui.same_line();
+    if let Some(format) = format_menu(ui, "Save All logs", &format!("Saves the log of every target to {SAVE_DIR}/<target>")) {
+        *save_all_logs = Some(format)
+    }
+    ui.same_line();
+    if ui.button("Clear") {
+        clear = Some(target.map(str::to_string))
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text(if target.is_some() { "Removes the messages of this tab" } else { "Removes every message" })
+    }
+    ui.same_line();
+    let disabled_token = ui.begin_disabled(view.selection.is_none());
+    if ui.button("Copy") {
+        ui.set_clipboard_text(view.selected_text(log, target.is_none(), *timestamps))
+    }
+    disabled_token.end();
+    if ui.is_item_hovered_with_flags(imgui::ItemHoveredFlags::ALLOW_WHEN_DISABLED) {
+        ui.tooltip_text("Copies the selected messages, also Ctrl+C. Click a message to select it, Shift+click to select up to it")
+    }
+    if let Some(target) = target {
+        ui.same_line();
+        // The plugin's own filter if it has one
+        let plugin = log.plugin();
+        target_level_combo(ui, target, plugin, filters, plugins);
+    }
+    if log.evicted() > 0 {
+        ui.text_disabled(format!("{} older messages were dropped", log.evicted()))
+    }
+
+    ui.child_window("lines").horizontal_scrollbar(true).build(|| {
+        // Every line has the same height, long messages only show their first line
+        let clipper = ListClipper::new(view.matches.len() as i32).begin(ui);
+        for row in clipper.iter() {
+            let index = view.matches[row as usize];
+            let Some(record) = log.get(index) else {
+                continue
+            };
+            let _id = ui.push_id_usize(index as usize);
+            let color = ui.push_style_color(StyleColor::Text, level_color(record.level));
+            let clicked = ui.selectable_config(line(record, target.is_none(), *timestamps, false))
+                .selected(view.is_selected(index))
+                .build();
+            color.pop();
+            if clicked {
+                view.select(index, ui.io().key_shift)
+            }
+            if ui.is_item_hovered() {
+                ui.tooltip(|| record_details(ui, record))
+            }
+        }
+
+        if view.selection.is_some() && ui.is_window_focused() && ui.io().key_ctrl && ui.is_key_pressed(Key::C) {
+            ui.set_clipboard_text(view.selected_text(log, target.is_none(), *timestamps))
+        }
+        if *auto_scroll && ui.scroll_y() >= ui.scroll_max_y() {
+            ui.set_scroll_here_y_with_ratio(1.0)
+        }
+    });
+
+    clear
+}
+
+/// A record on one line, or with its whole message when `full`.
+fn line(record: &LogRecord, with_target: bool, timestamps: bool, full: bool) -> String {
+    let mut line = String::new();
+    if timestamps {
+        let _ = write!(line, "{} ", time_of_day(record.timestamp));
+    }
+    if with_target {
+        let _ = write!(line, "[{}] ", record.target);
+    }
+    if full {
+        let _ = write!(line, "{record}");
+        return line
+    }
+
+    let mut message_lines = record.message.lines();
+    let _ = write!(line, "{} - {}", record.level, message_lines.next().unwrap_or_default());
+    for (key, value) in &record.fields {
+        let _ = write!(line, " {key}={value}");
+    }
+    let more_lines = message_lines.count();
+    if more_lines > 0 {
+        let _ = write!(line, " (+{more_lines} lines)");
+    }
+
+    line
+}
+
+/// `HH:MM:SS.mmm`, in UTC.
+fn time_of_day(time: SystemTime) -> String {
+    let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+    let seconds = since_epoch.as_secs() % 86_400;
+    format!("{:02}:{:02}:{:02}.{:03}", seconds / 3600, seconds / 60 % 60, seconds % 60, since_epoch.subsec_millis())
+}
+
+/// The whole message, and where it comes from.
+fn record_details(ui: &Ui, record: &LogRecord) {
+    ui.text(record.to_string());
+    if let Some(plugin) = &record.plugin {
+        ui.text_disabled(format!("Plugin: {plugin}"))
+    }
+    ui.text_disabled(format!("Target: {}", record.target));
+    if let Some(location) = record.location() {
+        ui.text_disabled(format!("At: {location}"))
+    }
+}
+
+/// A button that opens a menu of the formats. Returns the format chosen.
+fn format_menu(ui: &Ui, label: &str, tooltip: &str) -> Option<LogFormat> {
+    if ui.button(label) {
+        ui.open_popup(label)
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text(format!("{tooltip}.txt, .jsonl or .csv"))
+    }
+    let mut chosen = None;
+    ui.popup(label, || {
+        for format in LogFormat::ALL {
+            if ui.menu_item(format!("{} (.{})", format.label(), format.extension())) {
+                chosen = Some(format)
+            }
+        }
+    });
+
+    chosen
+}
+
+/// Exports `log` to `logs/<name>.<extension>`.
+fn save_log(name: &str, log: &TargetLog, format: LogFormat) {
+    let path = PathBuf::from(SAVE_DIR).join(format!("{}.{}", logs::file_name(name), format.extension()));
+    match logs::export(&path, format, log.records()) {
+        Ok(()) => info!("Saved the log of {name} to {}", path.display()),
+        Err(e) => error!("Could not save the log of {name} to {}\n\t{e}", path.display()),
+    }
+}
+
+/// A filter for xtea and one per active plugin, replacing xtea's for that plugin.
+fn log_filters_editor(ui: &Ui, editor: &mut LogFiltersEditor, plugins: &mut Plugins) {
+    ui.text_disabled("Like RUST_LOG: a default level, then target=level, e.g. info,my_plugin=debug,wgpu=off");
+    let mut apply = false;
+    if let Some(_table) = ui.begin_table_with_flags("log_filters", 2, imgui::TableFlags::SIZING_STRETCH_PROP) {
+        ui.table_next_column();
+        ui.text("xtea");
+        ui.table_next_column();
+        ui.set_next_item_width(-1.0);
+        apply |= ui.input_text("##xtea", &mut editor.global).enter_returns_true(true).build();
+
+        let active = plugins.all_plugins.iter().filter(|entry| plugins.loaded_plugins.contains_key(&entry.id));
+        for entry in active {
+            ui.table_next_column();
+            ui.text(&entry.name);
+            ui.table_next_column();
+            ui.set_next_item_width(-1.0);
+            let filter = editor.plugins.entry(entry.name.clone()).or_default();
+            apply |= ui.input_text(format!("##{}", entry.id), filter).hint("same as xtea").enter_returns_true(true).build();
+        }
+    }
+    if apply {
+        match editor.parse() {
+            Ok(filters) => {
+                editor.error = None;
+                plugins.set_log_filters(filters)
+            },
+            Err(e) => editor.error = Some(e),
+        }
+    }
+    if let Some(error) = &editor.error {
+        ui.text_colored(level_color(Level::Error), error)
+    }
+}
+
+/// Sets the level of `target`, in the filter of `plugin` if it has its own.
+fn target_level_combo(ui: &Ui, target: &str, plugin: Option<&str>, editor: &mut LogFiltersEditor, plugins: &mut Plugins) {
+    let mut filters = IMGUI_LOGGER.filters();
+    let filter = match plugin {
+        Some(plugin) if filters.plugins.contains_key(plugin) => filters.plugins.get_mut(plugin).expect("checked above"),
+        _ => &mut filters.global,
+    };
+    let default = format!("default ({})", filter.default_level().as_str().to_lowercase());
+    let mut items = vec![default.as_str()];
+    items.extend(["off", "error", "warn", "info", "debug", "trace"]);
+    // Off is 0, Trace is 5
+    let mut selected = filter.directive(target).map_or(0, |level| level as usize + 1);
+    ui.set_next_item_width(150.0);
+    if ui.combo_simple_string("Level", &mut selected, &items) {
+        let level = selected.checked_sub(1).and_then(|level| LevelFilter::iter().nth(level));
+        filter.set_directive(target, level);
+        *editor = LogFiltersEditor::new(&filters);
+        plugins.set_log_filters(filters)
+    }
+    if ui.is_item_hovered() {
+        ui.tooltip_text(format!("The most verbose messages of {target} that are kept"))
+    }
+}
+
+fn level_color(level: Level) -> [f32; 4] {
+    match level {
+        Level::Error => [1.0, 0.4, 0.4, 1.0],
+        Level::Warn => [1.0, 0.8, 0.3, 1.0],
+        Level::Info => [1.0, 1.0, 1.0, 1.0],
+        Level::Debug | Level::Trace => [0.6, 0.6, 0.6, 1.0],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+
+    use log::Level;
+
+    use super::*;
+
+    fn push(logs: &mut LogStore, target: &str, message: &str) {
+        logs.push(LogRecord {
+            level: Level::Info,
+            timestamp: SystemTime::now(),
+            target: target.to_string(),
+            plugin: None,
+            module_path: None,
+            file: None,
+            line: None,
+            message: message.to_string(),
+            fields: Vec::new(),
+        })
+    }
+
+    const EVERYTHING: LineFilter = LineFilter { levels: [true; 5], search: None };
+
+    #[test]
+    fn selection_is_kept_when_older_records_are_evicted() {
+        let mut logs = LogStore::new(3, 3, LogSinks::new(Vec::new()));
+        let mut view = LogView::default();
+        push(&mut logs, "a", "a0");
+        push(&mut logs, "a", "a1");
+        view.update(logs.all(), &EVERYTHING, 0, None);
+        view.select(1, false);
+
+        push(&mut logs, "a", "a2");
+        push(&mut logs, "a", "a3");
+        view.update(logs.all(), &EVERYTHING, 0, None);
+        assert_eq!(view.matches, [1, 2, 3]);
+        assert_eq!(view.selected_text(logs.all(), false, false), "INFO - a1");
+    }
+
+    #[test]
+    fn selection_is_dropped_when_the_indexes_change() {
+        let mut logs = LogStore::new(10, 10, LogSinks::new(Vec::new()));
+        let mut view = LogView::default();
+        push(&mut logs, "a", "a0");
+        push(&mut logs, "b", "b0");
+        view.update(logs.all(), &EVERYTHING, 0, None);
+        view.select(0, false);
+
+        // b0 takes the index of a0
+        logs.clear(Some("a"));
+        view.update(logs.all(), &EVERYTHING, 0, None);
+        assert_eq!(view.matches, [0]);
+        assert_eq!(view.selection, None);
+    }
+
+    #[test]
+    fn selection_is_kept_when_the_filter_changes() {
+        let mut logs = LogStore::new(10, 10, LogSinks::new(Vec::new()));
+        let mut view = LogView::default();
+        push(&mut logs, "a", "a0");
+        view.update(logs.all(), &EVERYTHING, 0, None);
+        view.select(0, false);
+
+        view.update(logs.all(), &LineFilter { levels: [false; 5], search: None }, 1, None);
+        assert!(view.matches.is_empty());
+        assert_eq!(view.selection, Some((0, 0)));
+    }
+}
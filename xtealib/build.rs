@@ -0,0 +1,28 @@
+use std::{path::Path, process::Command};
+
+/// Records the compiler and the imgui-rs release that build xtealib, so the host can report when a plugin was built with others.
+fn main() {
+    let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+    let verbose_version = Command::new(rustc)
+        .arg("-vV")
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+
+    let release = verbose_version.lines().next().unwrap_or("unknown");
+    let commit_hash = verbose_version.lines()
+        .find_map(|line| line.strip_prefix("commit-hash: "))
+        .unwrap_or("unknown");
+
+    println!("cargo:rustc-env=XTEALIB_RUSTC_VERSION={release}");
+    println!("cargo:rustc-env=XTEALIB_RUSTC_COMMIT_HASH={commit_hash}");
+
+    // imgui-sys is released along with imgui-rs, with the same version, and lives in `imgui-sys-<version>`
+    let imgui_sys_dir = std::env::var("DEP_IMGUI_THIRD_PARTY").unwrap_or_default();
+    let imgui_rs_version = Path::new(&imgui_sys_dir).ancestors()
+        .find_map(|dir| dir.file_name()?.to_str()?.strip_prefix("imgui-sys-"))
+        .unwrap_or("unknown");
+    println!("cargo:rustc-env=XTEALIB_IMGUI_RS_VERSION={imgui_rs_version}");
+    println!("cargo:rerun-if-env-changed=RUSTC");
+    println!("cargo:rerun-if-env-changed=DEP_IMGUI_THIRD_PARTY");
+}
@@ -0,0 +1,166 @@
+//! Version handshake between xtea and its plugins.
+//!
+//! Every plugin exports an [`AbiDescriptor`] (see [`declare_plugin_abi`](crate::declare_plugin_abi))
+//! that the host checks before calling anything else. The plugin API is a C ABI, and each side draws
+//! with its own imgui-rs, so only the API level and the Dear ImGui version (whose context both sides share)
+//! have to match. Differences in the imgui-rs, xtealib or compiler versions are only reported.
+
+use std::{ffi::{c_char, CStr}, fmt::Display};
+
+/// Bumped every time the [`PluginVTable`](crate::plugin::PluginVTable) or [`HostApi`](crate::plugin::HostApi)
+/// change in a way that isn't backwards compatible.
+pub const API_LEVEL: u32 = 4;
+
+/// Name of the function that returns the plugin's [`AbiDescriptor`].
+pub const ABI_SYMBOL: &str = "xtea_plugin_abi";
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct AbiDescriptor {
+    pub api_level: u32,
+    pub xtealib_version: *const c_char,
+    pub imgui_version: *const c_char,
+    pub rustc_version: *const c_char,
+    pub rustc_commit_hash: *const c_char,
+    pub imgui_rs_version: *const c_char,
+}
+
+impl AbiDescriptor {
+    /// The descriptor of whoever calls this, host or plugin.
+    pub fn current() -> AbiDescriptor {
+        AbiDescriptor {
+            api_level: API_LEVEL,
+            xtealib_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr().cast(),
+            imgui_version: unsafe { imgui::sys::igGetVersion() },
+            rustc_version: concat!(env!("XTEALIB_RUSTC_VERSION"), "\0").as_ptr().cast(),
+            rustc_commit_hash: concat!(env!("XTEALIB_RUSTC_COMMIT_HASH"), "\0").as_ptr().cast(),
+            imgui_rs_version: concat!(env!("XTEALIB_IMGUI_RS_VERSION"), "\0").as_ptr().cast(),
+        }
+    }
+
+    /// Checks that a plugin with this descriptor can be used by a host with the `host` descriptor.
+    ///
+    /// # Safety
+    /// The string pointers of both descriptors must be valid, nul-terminated strings.
+    pub unsafe fn check_compatible(&self, host: &AbiDescriptor) -> Result<(), AbiMismatch> {
+        if self.api_level != host.api_level {
+            return Err(AbiMismatch {
+                component: "plugin API level",
+                plugin: self.api_level.to_string(),
+                host: host.api_level.to_string(),
+            })
+        }
+
+        let plugin_imgui = CStr::from_ptr(self.imgui_version).to_string_lossy();
+        let host_imgui = CStr::from_ptr(host.imgui_version).to_string_lossy();
+        if plugin_imgui != host_imgui {
+            return Err(AbiMismatch {
+                component: "Dear ImGui",
+                plugin: plugin_imgui.into_owned(),
+                host: host_imgui.into_owned(),
+            })
+        }
+
+        Ok(())
+    }
+
+    /// Differences with the host that don't prevent loading the plugin, to help diagnose it.
+    ///
+    /// # Safety
+    /// The string pointers of both descriptors must be valid, nul-terminated strings.
+    pub unsafe fn harmless_differences(&self, host: &AbiDescriptor) -> Vec<AbiMismatch> {
+        let fields = [
+            ("imgui-rs", self.imgui_rs_version, host.imgui_rs_version),
+            ("xtealib", self.xtealib_version, host.xtealib_version),
+            ("rustc", self.rustc_commit_hash, host.rustc_commit_hash),
+        ];
+        let mut differences = Vec::new();
+        for (component, plugin, host_field) in fields {
+            let plugin = CStr::from_ptr(plugin).to_string_lossy();
+            let host_field = CStr::from_ptr(host_field).to_string_lossy();
+            if plugin == host_field {
+                continue
+            }
+            let (plugin, host_field) = if component == "rustc" {
+                (
+                    format!("{} ({plugin})", CStr::from_ptr(self.rustc_version).to_string_lossy()),
+                    format!("{} ({host_field})", CStr::from_ptr(host.rustc_version).to_string_lossy()),
+                )
+            } else {
+                (plugin.into_owned(), host_field.into_owned())
+            };
+            differences.push(AbiMismatch { component, plugin, host: host_field })
+        }
+
+        differences
+    }
+}
+
+/// A difference between a plugin's [`AbiDescriptor`] and the host's.
+#[derive(Debug)]
+pub struct AbiMismatch {
+    pub component: &'static str,
+    pub plugin: String,
+    pub host: String,
+}
+
+impl Display for AbiMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "plugin was built with {} {}, but xtea was built with {} {}",
+            self.component, self.plugin, self.component, self.host
+        )
+    }
+}
+
+/// Exports the [`AbiDescriptor`] of the plugin. Already done by [`xtea_plugin`](crate::xtea_plugin).
+#[macro_export]
+macro_rules! declare_plugin_abi {
+    () => {
+        #[no_mangle]
+        pub extern "C" fn xtea_plugin_abi() -> $crate::abi::AbiDescriptor {
+            $crate::abi::AbiDescriptor::current()
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_the_api_level_and_dear_imgui_must_match() {
+        let host = AbiDescriptor::current();
+        assert!(unsafe { host.check_compatible(&host) }.is_ok());
+        assert!(unsafe { host.harmless_differences(&host) }.is_empty());
+
+        let older = AbiDescriptor { api_level: API_LEVEL - 1, ..host };
+        let mismatch = unsafe { older.check_compatible(&host) }.unwrap_err();
+        assert_eq!(mismatch.component, "plugin API level");
+
+        let other_imgui = AbiDescriptor { imgui_version: c"1.0".as_ptr(), ..host };
+        let mismatch = unsafe { other_imgui.check_compatible(&host) }.unwrap_err();
+        assert_eq!(mismatch.to_string(), format!(
+            "plugin was built with Dear ImGui 1.0, but xtea was built with Dear ImGui {}",
+            unsafe { CStr::from_ptr(host.imgui_version) }.to_string_lossy()
+        ));
+    }
+
+    #[test]
+    fn other_toolchains_are_only_reported() {
+        let host = AbiDescriptor::current();
+        let plugin = AbiDescriptor {
+            xtealib_version: c"0.0.1".as_ptr(),
+            rustc_version: c"rustc 1.70.0".as_ptr(),
+            rustc_commit_hash: c"abc".as_ptr(),
+            ..host
+        };
+        assert!(unsafe { plugin.check_compatible(&host) }.is_ok());
+
+        let differences = unsafe { plugin.harmless_differences(&host) };
+        let components = differences.iter().map(|difference| difference.component).collect::<Vec<_>>();
+        assert_eq!(components, ["xtealib", "rustc"]);
+        assert_eq!(differences[1].plugin, "rustc 1.70.0 (abc)");
+    }
+}
@@ -0,0 +1,177 @@
+//! Turns an [`XteaPlugin`] into a [`PluginVTable`].
+
+use std::{error::Error, path::Path, time::{Duration, Instant}};
+
+use imgui::Ui;
+
+use crate::{
+    ffi::{FfiSlice, FfiStr, UiHandle},
+    plugin::{connect_host, set_log_filter, PluginInstance, PluginVTable, ByteSink},
+    panic,
+    tasks,
+};
+
+/// A plugin. The host owns its only instance and never calls it from two threads at once.
+///
+/// ```ignore
+/// struct MyPlugin {
+///     show_window: bool
+/// }
+///
+/// impl XteaPlugin for MyPlugin {
+///     fn new() -> Result<Self, Box<dyn Error>> {
+///         Ok(MyPlugin { show_window: true })
+///     }
+///
+///     fn name() -> &'static str {
+///         "My Plugin Name"
+///     }
+///
+///     fn build_ui(&mut self, ui: &Ui) {
+///         if self.show_window {
+///             ui.window("My Plugin").focus_on_appearing(false).build(|| ui.text("Hello"));
+///         }
+///     }
+///
+///     fn view_submenu(&mut self, ui: &Ui) {
+///         ui.checkbox("My Plugin", &mut self.show_window);
+///     }
+/// }
+///
+/// xtealib::xtea_plugin!(MyPlugin);
+/// ```
+pub trait XteaPlugin: Send + Sized + 'static {
+    /// Creates the plugin. Called once, in a separate thread, so it can do slow initialization.
+    ///
+    /// The error is shown to the user, who can retry once they fixed its cause.
+    fn new() -> Result<Self, Box<dyn Error>>;
+
+    /// Must be unique among all plugins.
+    fn name() -> &'static str;
+
+    /// Called every frame.
+    fn build_ui(&mut self, ui: &Ui);
+
+    /// Called every frame while the "View" menu is open.
+    fn view_submenu(&mut self, _ui: &Ui) {}
+
+    /// Called before the plugin is unloaded, and when xtea exits.
+    /// Every task started with [`tasks::spawn`] must finish before `deadline`,
+    /// from then on [`tasks::is_cancelled`] returns `true`.
+    fn shutdown(&mut self, _deadline: Instant) {}
+
+    /// Called before the plugin is reloaded. The returned bytes are given to the new instance's
+    /// [`restore_state`](XteaPlugin::restore_state), so reloading keeps the plugin's windows and values.
+    ///
+    /// With the `serde` feature, `xtea_plugin!(MyPlugin, serde_state)` implements both for a
+    /// plugin that derives `Serialize` and `Deserialize`.
+    fn save_state(&self) -> Option<Vec<u8>> {
+        None
+    }
+
+    /// Called right after [`new`](XteaPlugin::new) when the plugin is reloaded,
+    /// with what the previous instance's [`save_state`](XteaPlugin::save_state) returned.
+    fn restore_state(&mut self, _state: &[u8]) {}
+
+    /// Called with the files given on xtea's command line, once every plugin is initialized.
+    /// Returns whether this plugin opened the file, otherwise the next plugin is asked.
+    fn open_file(&mut self, _path: &Path) -> bool {
+        false
+    }
+}
+
+impl PluginVTable {
+    pub const fn for_plugin<T: XteaPlugin>() -> PluginVTable {
+        PluginVTable {
+            size: std::mem::size_of::<PluginVTable>(),
+            get_name: get_name::<T>,
+            connect_host,
+            create: create::<T>,
+            destroy: destroy::<T>,
+            build_ui: Some(build_ui::<T>),
+            view_submenu: Some(view_submenu::<T>),
+            shutdown: Some(shutdown::<T>),
+            running_tasks: Some(tasks::running_tasks),
+            save_state: Some(save_state::<T>),
+            restore_state: Some(restore_state::<T>),
+            take_panic: Some(panic::take_panic),
+            try_create: Some(try_create::<T>),
+            open_file: Some(open_file::<T>),
+            set_log_filter: Some(set_log_filter),
+            join_tasks: Some(tasks::join_tasks),
+        }
+    }
+}
+
+/// # Safety
+/// `instance` must come from [`create`] with the same `T`, and not be destroyed yet.
+unsafe fn instance<'a, T: XteaPlugin>(instance: *mut PluginInstance) -> &'a mut T {
+    &mut *instance.cast::<T>()
+}
+
+// Every call is wrapped in `panic::catch`, see `crate::panic`
+
+extern "C" fn get_name<T: XteaPlugin>() -> FfiStr<'static> {
+    FfiStr::new(panic::catch("", T::name))
+}
+
+extern "C" fn create<T: XteaPlugin>() -> *mut PluginInstance {
+    try_create::<T>(&mut ByteSink::for_vec(&mut Vec::new()))
+}
+
+extern "C" fn try_create<T: XteaPlugin>(error: &mut ByteSink) -> *mut PluginInstance {
+    tasks::set_cancelled(false);
+    panic::catch(std::ptr::null_mut(), || match T::new() {
+        Ok(plugin) => Box::into_raw(Box::new(plugin)).cast(),
+        Err(e) => {
+            error.write(e.to_string().as_bytes());
+            std::ptr::null_mut()
+        },
+    })
+}
+
+extern "C" fn destroy<T: XteaPlugin>(instance: *mut PluginInstance) {
+    panic::catch((), || drop(unsafe { Box::from_raw(instance.cast::<T>()) }))
+}
+
+extern "C" fn build_ui<T: XteaPlugin>(plugin: *mut PluginInstance, ui: UiHandle) {
+    panic::catch((), || unsafe { ui.with_ui(|ui| instance::<T>(plugin).build_ui(ui)) })
+}
+
+extern "C" fn view_submenu<T: XteaPlugin>(plugin: *mut PluginInstance, ui: UiHandle) {
+    panic::catch((), || unsafe { ui.with_ui(|ui| instance::<T>(plugin).view_submenu(ui)) })
+}
+
+extern "C" fn shutdown<T: XteaPlugin>(plugin: *mut PluginInstance, timeout_ms: u64) {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    tasks::set_cancelled(true);
+    panic::catch((), || unsafe { instance::<T>(plugin).shutdown(deadline) })
+}
+
+extern "C" fn save_state<T: XteaPlugin>(plugin: *const PluginInstance, sink: &mut ByteSink) {
+    if let Some(state) = panic::catch(None, || unsafe { &*plugin.cast::<T>() }.save_state()) {
+        sink.write(&state)
+    }
+}
+
+extern "C" fn restore_state<T: XteaPlugin>(plugin: *mut PluginInstance, state: FfiSlice<u8>) {
+    panic::catch((), || unsafe { instance::<T>(plugin).restore_state(state.as_slice()) })
+}
+
+extern "C" fn open_file<T: XteaPlugin>(plugin: *mut PluginInstance, path: FfiStr) -> bool {
+    panic::catch(false, || unsafe { instance::<T>(plugin).open_file(Path::new(path.as_str())) })
+}
+
+/// Exports everything xtea needs from a type implementing [`XteaPlugin`].
+///
+/// `xtea_plugin!(MyPlugin, serde_state)` also keeps the whole plugin across reloads,
+/// see [`state`](crate::state).
+#[macro_export]
+macro_rules! xtea_plugin {
+    ($plugin:ty) => {
+        $crate::declare_plugin!($crate::plugin::PluginVTable::for_plugin::<$plugin>());
+    };
+    ($plugin:ty, serde_state) => {
+        $crate::declare_plugin!($crate::plugin::PluginVTable::for_serde_plugin::<$plugin>());
+    };
+}
@@ -0,0 +1,100 @@
+//! Types that can cross the boundary between xtea and a plugin, whatever compiler built each side.
+
+use std::{cell::UnsafeCell, marker::PhantomData};
+
+use imgui::{sys::ImGuiContext, Ui, UiBuffer};
+
+/// A borrowed utf-8 string. The memory stays owned by the side that created it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiStr<'a> {
+    ptr: *const u8,
+    len: usize,
+    _lifetime: PhantomData<&'a str>,
+}
+
+impl<'a> FfiStr<'a> {
+    pub const fn new(string: &'a str) -> FfiStr<'a> {
+        FfiStr {
+            ptr: string.as_ptr(),
+            len: string.len(),
+            _lifetime: PhantomData,
+        }
+    }
+
+    /// # Safety
+    /// The string must have been created with [`FfiStr::new`] and its memory must still be alive.
+    pub unsafe fn as_str(&self) -> &'a str {
+        std::str::from_utf8_unchecked(std::slice::from_raw_parts(self.ptr, self.len))
+    }
+}
+
+impl<'a> From<&'a str> for FfiStr<'a> {
+    fn from(string: &'a str) -> Self {
+        FfiStr::new(string)
+    }
+}
+
+/// A borrowed slice. The memory stays owned by the side that created it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct FfiSlice<'a, T> {
+    ptr: *const T,
+    len: usize,
+    _lifetime: PhantomData<&'a [T]>,
+}
+
+impl<'a, T> FfiSlice<'a, T> {
+    pub const fn new(slice: &'a [T]) -> FfiSlice<'a, T> {
+        FfiSlice {
+            ptr: slice.as_ptr(),
+            len: slice.len(),
+            _lifetime: PhantomData,
+        }
+    }
+
+    /// # Safety
+    /// The slice must have been created with [`FfiSlice::new`] and its memory must still be alive.
+    pub unsafe fn as_slice(&self) -> &'a [T] {
+        std::slice::from_raw_parts(self.ptr, self.len)
+    }
+}
+
+impl<'a, T> From<&'a [T]> for FfiSlice<'a, T> {
+    fn from(slice: &'a [T]) -> Self {
+        FfiSlice::new(slice)
+    }
+}
+
+/// The Dear ImGui context of the current frame. Only the C context crosses the boundary,
+/// each side draws with its own imgui-rs [`Ui`].
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy)]
+pub struct UiHandle(*mut ImGuiContext);
+
+impl UiHandle {
+    /// The handle of the frame `_ui` is building.
+    pub fn new(_ui: &Ui) -> UiHandle {
+        UiHandle(unsafe { imgui::sys::igGetCurrentContext() })
+    }
+
+    /// Calls `f` with a [`Ui`] of the calling library, drawing in the handle's context.
+    ///
+    /// # Safety
+    /// Must only be called during the frame the handle was created for, on the thread building it.
+    pub unsafe fn with_ui<R>(self, f: impl FnOnce(&Ui) -> R) -> R {
+        // The plugin's copy of Dear ImGui has its own current context
+        if imgui::sys::igGetCurrentContext() != self.0 {
+            imgui::sys::igSetCurrentContext(self.0)
+        }
+        f(&local_ui())
+    }
+}
+
+/// A `Ui` of this library, so its scratch buffer is only ever allocated and freed by this library.
+fn local_ui() -> Ui {
+    // `Ui` only holds its scratch buffer, but imgui-rs only makes one for a context it created.
+    // This relies on the private layout of `Ui`, which is why xtealib's Cargo.toml pins imgui-rs with `=`:
+    // the transmute only checks the size, check imgui-rs's `Ui` again before changing that pin
+    unsafe { std::mem::transmute::<UnsafeCell<UiBuffer>, Ui>(UnsafeCell::new(UiBuffer::new(1024))) }
+}
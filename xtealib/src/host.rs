@@ -0,0 +1,81 @@
+//! Loading and unloading plugins, shared by xtea and the test harness so both drive plugins the same way.
+
+use std::{ptr::NonNull, time::{Duration, Instant}};
+
+use crate::{
+    abi::{AbiDescriptor, AbiMismatch, ABI_SYMBOL},
+    plugin::{EntryPoint, PluginInstance, PluginVTable, ENTRY_POINT_SYMBOL},
+};
+
+/// Checks the plugin's ABI descriptor and, if it matches the host's, reads the plugin's vtable.
+///
+/// `abi` and `entry_point` are the library's [`ABI_SYMBOL`] and [`ENTRY_POINT_SYMBOL`], `None` if it doesn't export them.
+/// Returns the vtable along with the differences with the host that don't prevent using it,
+/// or why the plugin is incompatible.
+///
+/// # Safety
+/// `abi` and `entry_point` must have the signature of the symbols they were looked up with.
+pub unsafe fn read_plugin(
+    abi: Option<extern "C" fn() -> AbiDescriptor>,
+    entry_point: Option<EntryPoint>,
+) -> Result<(PluginVTable, Vec<AbiMismatch>), String> {
+    let Some(abi) = abi else {
+        return Err(format!("it doesn't export {ABI_SYMBOL}, declare it with `xtealib::xtea_plugin!`"))
+    };
+    let plugin_abi = abi();
+    let host_abi = AbiDescriptor::current();
+    plugin_abi.check_compatible(&host_abi).map_err(|mismatch| mismatch.to_string())?;
+    let differences = plugin_abi.harmless_differences(&host_abi);
+
+    let Some(entry_point) = entry_point else {
+        return Err(format!("it doesn't export {ENTRY_POINT_SYMBOL}"))
+    };
+    let vtable = entry_point();
+    if vtable.is_null() {
+        return Err(format!("{ENTRY_POINT_SYMBOL} returned null"))
+    }
+    // Read the size first, a plugin built with an older xtealib has a smaller vtable
+    let vtable_size = vtable.cast::<usize>().read();
+    if vtable_size < PluginVTable::REQUIRED_SIZE {
+        return Err(format!(
+            "its vtable has {vtable_size} bytes, but the required functions take {}", PluginVTable::REQUIRED_SIZE
+        ))
+    }
+
+    Ok((PluginVTable::read(vtable), differences))
+}
+
+/// Lets the plugin stop its tasks within `timeout` and destroys `instance`. `shutdown` is skipped
+/// if the plugin `crashed`, since the instance can only be destroyed then.
+/// Returns how many tasks are still running, the library can't be closed until it's 0.
+pub fn shutdown(
+    vtable: &PluginVTable,
+    instance: Option<NonNull<PluginInstance>>,
+    crashed: bool,
+    timeout: Duration,
+) -> usize {
+    let deadline = Instant::now() + timeout;
+    if let (Some(instance), Some(shutdown), false) = (instance, vtable.shutdown, crashed) {
+        shutdown(instance.as_ptr(), timeout.as_millis() as u64)
+    }
+    wait_for_tasks(vtable, deadline);
+    if let Some(instance) = instance {
+        (vtable.destroy)(instance.as_ptr())
+    }
+
+    // Destroying the instance may have stopped some
+    wait_for_tasks(vtable, Instant::now())
+}
+
+/// Waits until the plugin's tasks are over, or until `deadline`. Returns how many are still running.
+fn wait_for_tasks(vtable: &PluginVTable, deadline: Instant) -> usize {
+    if let Some(join_tasks) = vtable.join_tasks {
+        return join_tasks(deadline.saturating_duration_since(Instant::now()).as_millis() as u64)
+    }
+    let running_tasks = || vtable.running_tasks.map_or(0, |running_tasks| running_tasks());
+    while running_tasks() > 0 && Instant::now() < deadline {
+        std::thread::sleep(Duration::from_millis(10))
+    }
+
+    running_tasks()
+}
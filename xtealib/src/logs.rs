@@ -0,0 +1,460 @@
+//! Log records as xtea keeps them, whether they come from xtea itself or from a plugin.
+
+use std::{
+    collections::BTreeMap,
+    fmt::Display,
+    str::FromStr,
+    sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::{self, Receiver, SyncSender}, Arc, Mutex, OnceLock, PoisonError, RwLock},
+    time::SystemTime,
+};
+
+use log::{kv::{self, VisitSource}, Level, LevelFilter, Record, SetLoggerError};
+
+use crate::plugin::FfiLogRecord;
+
+/// A log record, with everything the `log` macros captured.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogRecord {
+    pub level: Level,
+    /// When the host received it.
+    pub timestamp: SystemTime,
+    pub target: String,
+    /// The name of the plugin that logged it. `None` for xtea itself,
+    /// and for plugins built with an xtealib that didn't send it. Shared by all the records of the plugin.
+    pub plugin: Option<Arc<str>>,
+    pub module_path: Option<String>,
+    pub file: Option<String>,
+    pub line: Option<u32>,
+    pub message: String,
+    /// The key-values of the record, like `path` in `info!(path:?; "Opened")`, formatted with `Display`.
+    pub fields: Vec<(String, String)>,
+}
+
+impl LogRecord {
+    /// Copies a record logged in this binary.
+    pub fn new(record: &Record) -> LogRecord {
+        LogRecord {
+            level: record.level(),
+            timestamp: SystemTime::now(),
+            target: record.target().to_string(),
+            plugin: None,
+            module_path: record.module_path().map(str::to_string),
+            file: record.file().map(str::to_string),
+            line: record.line(),
+            message: record.args().to_string(),
+            fields: fields(record),
+        }
+    }
+
+    /// Copies a record a plugin sent.
+    ///
+    /// # Safety
+    /// `record` must come from a plugin, which keeps its strings alive during the call.
+    pub unsafe fn from_ffi(record: &FfiLogRecord, plugin: Option<Arc<str>>) -> LogRecord {
+        let optional = |string: &str| (!string.is_empty()).then(|| string.to_string());
+        LogRecord {
+            level: record.level(),
+            timestamp: SystemTime::now(),
+            target: record.target.as_str().to_string(),
+            plugin,
+            module_path: optional(record.module_path.as_str()),
+            file: optional(record.file.as_str()),
+            line: (record.line != 0).then_some(record.line),
+            message: record.message.as_str().to_string(),
+            fields: record.fields.as_slice().iter()
+                .map(|field| (field.key.as_str().to_string(), field.value.as_str().to_string()))
+                .collect(),
+        }
+    }
+
+    /// `file:line`, if the record has them.
+    pub fn location(&self) -> Option<String> {
+        let file = self.file.as_ref()?;
+        Some(match self.line {
+            Some(line) => format!("{file}:{line}"),
+            None => file.clone(),
+        })
+    }
+}
+
+/// `LEVEL - message key=value...`
+impl Display for LogRecord {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}", self.level, self.message)?;
+        for (key, value) in &self.fields {
+            write!(f, " {key}={value}")?
+        }
+
+        Ok(())
+    }
+}
+
+/// The key-values of `record`.
+pub(crate) fn fields(record: &Record) -> Vec<(String, String)> {
+    struct Collect(Vec<(String, String)>);
+
+    impl<'kvs> VisitSource<'kvs> for Collect {
+        fn visit_pair(&mut self, key: kv::Key<'kvs>, value: kv::Value<'kvs>) -> Result<(), kv::Error> {
+            self.0.push((key.to_string(), value.to_string()));
+            Ok(())
+        }
+    }
+
+    let mut fields = Collect(Vec::new());
+    // Collecting never fails
+    let _ = record.key_values().visit(&mut fields);
+
+    fields.0
+}
+
+/// Which records are kept, from directives like `RUST_LOG`'s: `info,my_plugin=debug,wgpu=off`.
+///
+/// A directive is `target=level`, `target` alone (every level), or `level` alone (every other target,
+/// `trace` if there is none).
+/// A record uses the directive with the longest target that starts its own target.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LogFilter {
+    default: LevelFilter,
+    /// Longest target first, then by name, last first.
+    directives: Vec<(String, LevelFilter)>,
+}
+
+impl LogFilter {
+    /// Every target at `level`.
+    pub const fn new(level: LevelFilter) -> LogFilter {
+        LogFilter { default: level, directives: Vec::new() }
+    }
+
+    /// The level of the targets without their own directive.
+    pub fn default_level(&self) -> LevelFilter {
+        self.default
+    }
+
+    pub fn set_default_level(&mut self, level: LevelFilter) {
+        self.default = level
+    }
+
+    /// The most verbose level of `target`.
+    pub fn level(&self, target: &str) -> LevelFilter {
+        self.directives.iter()
+            .find(|(prefix, _)| target.starts_with(prefix.as_str()))
+            .map_or(self.default, |&(_, level)| level)
+    }
+
+    /// The level `target` has its own directive for.
+    pub fn directive(&self, target: &str) -> Option<LevelFilter> {
+        self.directives.iter().find(|(prefix, _)| prefix == target).map(|&(_, level)| level)
+    }
+
+    /// Gives `target` its own level, or removes its directive.
+    pub fn set_directive(&mut self, target: &str, level: Option<LevelFilter>) {
+        self.directives.retain(|(prefix, _)| prefix != target);
+        if let Some(level) = level {
+            // Same-length targets by name, so equal filters have their directives in the same order
+            let index = self.directives.partition_point(|(prefix, _)| {
+                prefix.len() > target.len() || (prefix.len() == target.len() && prefix.as_str() > target)
+            });
+            self.directives.insert(index, (target.to_string(), level))
+        }
+    }
+
+    pub fn enabled(&self, target: &str, level: Level) -> bool {
+        level <= self.level(target)
+    }
+
+    /// The most verbose level of all the targets, for [`log::set_max_level`].
+    pub fn max_level(&self) -> LevelFilter {
+        self.directives.iter().map(|&(_, level)| level).fold(self.default, Ord::max)
+    }
+}
+
+/// Every target at `Trace`, besides wgpu and naga which log a lot about every frame.
+impl Default for LogFilter {
+    fn default() -> Self {
+        let mut filter = LogFilter::new(LevelFilter::Trace);
+        filter.set_directive("wgpu", Some(LevelFilter::Off));
+        filter.set_directive("naga", Some(LevelFilter::Off));
+        filter
+    }
+}
+
+impl FromStr for LogFilter {
+    type Err = String;
+
+    fn from_str(directives: &str) -> Result<Self, Self::Err> {
+        let mut filter = LogFilter::new(LevelFilter::Trace);
+        for directive in directives.split(',').map(str::trim).filter(|directive| !directive.is_empty()) {
+            let parse_level = |level: &str| level.trim().parse::<LevelFilter>()
+                .map_err(|_| format!("invalid level '{}' in '{directive}', expected off, error, warn, info, debug or trace", level.trim()));
+            match directive.split_once('=') {
+                Some((target, level)) => {
+                    let target = target.trim();
+                    if target.is_empty() {
+                        return Err(format!("missing target in '{directive}'"))
+                    }
+                    filter.set_directive(target, Some(parse_level(level)?))
+                },
+                None => match directive.parse::<LevelFilter>() {
+                    Ok(level) => filter.default = level,
+                    Err(_) => filter.set_directive(directive, Some(LevelFilter::Trace)),
+                },
+            }
+        }
+
+        Ok(filter)
+    }
+}
+
+/// The directives, in a form [`LogFilter::from_str`] reads back.
+impl Display for LogFilter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.default.as_str().to_lowercase())?;
+        // Shortest first, like they're usually written
+        for (target, level) in self.directives.iter().rev() {
+            write!(f, ",{target}={}", level.as_str().to_lowercase())?
+        }
+
+        Ok(())
+    }
+}
+
+/// The filter of xtea's own records, and the ones replacing it for some plugins.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LogFilters {
+    pub global: LogFilter,
+    /// By plugin name.
+    pub plugins: BTreeMap<String, LogFilter>,
+}
+
+impl LogFilters {
+    /// The filter of the records of `plugin`, or of xtea itself when `None`.
+    pub fn get(&self, plugin: Option<&str>) -> &LogFilter {
+        plugin.and_then(|plugin| self.plugins.get(plugin)).unwrap_or(&self.global)
+    }
+
+    pub fn max_level(&self) -> LevelFilter {
+        self.plugins.values().map(LogFilter::max_level).fold(self.global.max_level(), Ord::max)
+    }
+}
+
+/// How many records can wait for the Logs window, newer ones are dropped.
+pub const PENDING_CAPACITY: usize = 65_536;
+
+/// Keeps the records until the Logs window takes them.
+///
+/// Records from any thread go into a bounded channel, which never blocks the thread logging,
+/// so a plugin that logs faster than xtea takes the records only loses its newest ones.
+pub struct ImguiLogger {
+    /// Created with the first record.
+    channel: OnceLock<Channel>,
+    /// Records that didn't fit in the channel.
+    dropped: AtomicU64,
+    /// Also print every message to stderr.
+    stderr: AtomicBool,
+    filters: RwLock<Option<LogFilters>>,
+}
+
+struct Channel {
+    // Boxed, the channel allocates all its slots upfront
+    sender: SyncSender<Box<LogRecord>>,
+    // Only locked by whoever takes the records
+    receiver: Mutex<Receiver<Box<LogRecord>>>,
+}
+
+impl ImguiLogger {
+    pub const fn new() -> ImguiLogger {
+        ImguiLogger {
+            channel: OnceLock::new(),
+            dropped: AtomicU64::new(0),
+            stderr: AtomicBool::new(false),
+            filters: RwLock::new(None),
+        }
+    }
+
+    /// Keeps the records of the [default filter](LogFilter::default).
+    pub fn init(&'static self) -> Result<(), SetLoggerError> {
+        self.init_with_filter(LogFilter::default())
+    }
+
+    /// Like [`init`](ImguiLogger::init), with `level` for the targets of the default filter.
+    pub fn init_with_level(&'static self, level: LevelFilter) -> Result<(), SetLoggerError> {
+        let mut filter = LogFilter::default();
+        filter.set_default_level(level);
+        self.init_with_filter(filter)
+    }
+
+    pub fn init_with_filter(&'static self, filter: LogFilter) -> Result<(), SetLoggerError> {
+        log::set_logger(self)
+            .map(|()| self.set_filters(LogFilters { global: filter, plugins: BTreeMap::new() }))
+    }
+
+    /// The filters in use, the [default](LogFilter::default) ones before they're set.
+    pub fn filters(&self) -> LogFilters {
+        self.filters.read().unwrap_or_else(PoisonError::into_inner).clone().unwrap_or_default()
+    }
+
+    /// Replaces the filters. Plugins have their own copy, which the host updates.
+    pub fn set_filters(&self, filters: LogFilters) {
+        log::set_max_level(filters.max_level());
+        *self.filters.write().unwrap_or_else(PoisonError::into_inner) = Some(filters)
+    }
+
+    fn enabled(&self, plugin: Option<&str>, target: &str, level: Level) -> bool {
+        match &*self.filters.read().unwrap_or_else(PoisonError::into_inner) {
+            Some(filters) => filters.get(plugin).enabled(target, level),
+            None => LogFilter::default().enabled(target, level),
+        }
+    }
+
+    pub fn set_stderr(&self, stderr: bool) {
+        self.stderr.store(stderr, Ordering::Relaxed)
+    }
+
+    /// Adds a record that didn't go through `log`, like the ones plugins send.
+    pub fn push(&self, record: LogRecord) {
+        if self.enabled(record.plugin.as_deref(), &record.target, record.level) {
+            self.send(record)
+        }
+    }
+
+    fn send(&self, record: LogRecord) {
+        if self.stderr.load(Ordering::Relaxed) {
+            eprintln!("[{}] {record}", record.target)
+        }
+        let channel = self.channel.get_or_init(|| {
+            let (sender, receiver) = mpsc::sync_channel(PENDING_CAPACITY);
+            Channel { sender, receiver: Mutex::new(receiver) }
+        });
+        if channel.sender.try_send(Box::new(record)).is_err() {
+            self.dropped.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Takes up to `max` of the records logged since the last call, oldest first. Returns how many it took.
+    pub fn drain(&self, max: usize, mut f: impl FnMut(LogRecord)) -> usize {
+        let Some(channel) = self.channel.get() else {
+            return 0
+        };
+        let receiver = channel.receiver.lock().unwrap_or_else(|poisoned| poisoned.into_inner());
+
+        receiver.try_iter().take(max).map(|record| f(*record)).count()
+    }
+
+    /// Takes every record logged since the last call, oldest first.
+    pub fn clear(&self) -> Vec<LogRecord> {
+        let mut records = Vec::new();
+        self.drain(usize::MAX, |record| records.push(record));
+
+        records
+    }
+
+    /// How many records were dropped because they weren't taken fast enough.
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl Default for ImguiLogger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl log::Log for ImguiLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        self.enabled(None, metadata.target(), metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if log::Log::enabled(self, record.metadata()) {
+            self.send(LogRecord::new(record))
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filter(directives: &str) -> LogFilter {
+        directives.parse().unwrap()
+    }
+
+    #[test]
+    fn directives_give_targets_their_level() {
+        let filter = filter("a=debug,b::c=off,warn");
+        assert_eq!(filter.default_level(), LevelFilter::Warn);
+        assert_eq!(filter.level("a"), LevelFilter::Debug);
+        assert_eq!(filter.level("a::module"), LevelFilter::Debug);
+        assert_eq!(filter.level("b::c"), LevelFilter::Off);
+        assert_eq!(filter.level("b"), LevelFilter::Warn);
+        assert_eq!(filter.level("other"), LevelFilter::Warn);
+        assert!(filter.enabled("a", Level::Debug) && !filter.enabled("a", Level::Trace));
+        assert!(!filter.enabled("b::c::d", Level::Error));
+        assert_eq!(filter.max_level(), LevelFilter::Debug);
+    }
+
+    #[test]
+    fn the_longest_matching_target_wins() {
+        let filter = filter("my_plugin=info,my_plugin::net=trace,my_plugin::net::tls=off,my=error");
+        assert_eq!(filter.level("my_plugin::net::tls::handshake"), LevelFilter::Off);
+        assert_eq!(filter.level("my_plugin::net::http"), LevelFilter::Trace);
+        assert_eq!(filter.level("my_plugin::ui"), LevelFilter::Info);
+        assert_eq!(filter.level("my_other_plugin"), LevelFilter::Error);
+        assert_eq!(filter.level("wgpu"), LevelFilter::Trace);
+    }
+
+    #[test]
+    fn lone_targets_and_levels() {
+        let filter = filter(" my_plugin , error ,");
+        assert_eq!(filter.default_level(), LevelFilter::Error);
+        assert_eq!(filter.directive("my_plugin"), Some(LevelFilter::Trace));
+        assert_eq!("".parse::<LogFilter>(), Ok(LogFilter::new(LevelFilter::Trace)));
+    }
+
+    #[test]
+    fn invalid_directives_are_errors() {
+        assert_eq!(
+            "a=loud".parse::<LogFilter>(),
+            Err("invalid level 'loud' in 'a=loud', expected off, error, warn, info, debug or trace".to_string()),
+        );
+        assert_eq!("=debug".parse::<LogFilter>(), Err("missing target in '=debug'".to_string()));
+        assert!("info,a=".parse::<LogFilter>().is_err());
+    }
+
+    #[test]
+    fn display_is_read_back() {
+        assert_eq!(filter("a=debug,b::c=off,warn").to_string(), "warn,a=debug,b::c=off");
+        assert_eq!(LogFilter::default().to_string(), "trace,naga=off,wgpu=off");
+        for directives in ["a=debug,b::c=off,warn", "wgpu=off,naga=off", "info,x", "off"] {
+            let filter = filter(directives);
+            assert_eq!(filter.to_string().parse::<LogFilter>(), Ok(filter), "{directives}")
+        }
+    }
+
+    #[test]
+    fn set_directive_replaces_and_removes() {
+        let mut filter = filter("info,a=debug");
+        filter.set_directive("a", Some(LevelFilter::Error));
+        filter.set_directive("a::b", Some(LevelFilter::Trace));
+        assert_eq!(filter.to_string(), "info,a=error,a::b=trace");
+        filter.set_directive("a", None);
+        assert_eq!(filter.directive("a"), None);
+        assert_eq!(filter.level("a"), LevelFilter::Info);
+    }
+
+    #[test]
+    fn plugins_can_have_their_own_filter() {
+        let filters = LogFilters {
+            global: filter("info"),
+            plugins: BTreeMap::from([("Counter".to_string(), filter("error,counter::debug=trace"))]),
+        };
+        assert_eq!(filters.get(None).level("counter::debug"), LevelFilter::Info);
+        assert_eq!(filters.get(Some("Counter")).level("counter::debug"), LevelFilter::Trace);
+        assert_eq!(filters.get(Some("Counter")).level("xtea"), LevelFilter::Error);
+        assert_eq!(filters.get(Some("Other")).level("counter::debug"), LevelFilter::Info);
+        assert_eq!(filters.max_level(), LevelFilter::Trace);
+    }
+}
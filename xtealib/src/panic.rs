@@ -0,0 +1,59 @@
+//! Keeps a plugin's panics from unwinding into the host.
+//!
+//! Unwinding out of an `extern "C"` function aborts, so every function the host calls catches the
+//! plugin's panics, and keeps the last one until the host asks for it with `take_panic`.
+
+use std::{backtrace::Backtrace, cell::{Cell, RefCell}, panic::AssertUnwindSafe, sync::{Mutex, Once}};
+
+use log::error;
+
+use crate::plugin::ByteSink;
+
+static LAST_PANIC: Mutex<Option<String>> = Mutex::new(None);
+
+thread_local! {
+    /// Whether this thread is running a function called by the host.
+    static IN_HOST_CALL: Cell<bool> = const { Cell::new(false) };
+    static PANIC_REPORT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+static SET_HOOK: Once = Once::new();
+
+/// Replaces the default panic message with a report that includes the backtrace.
+pub(crate) fn set_panic_hook() {
+    // Once per library, connecting again must not chain the hook to itself
+    SET_HOOK.call_once(|| {
+        let previous_hook = std::panic::take_hook();
+        std::panic::set_hook(Box::new(move |info| {
+            if IN_HOST_CALL.get() {
+                PANIC_REPORT.set(Some(format!("{info}\n{}", Backtrace::force_capture())))
+            } else {
+                // A panic in one of the plugin's own threads, the host doesn't need to know about it.
+                // When the plugin is linked into the host (like in tests), it's also the host's own panic.
+                error!("{info}\n{}", Backtrace::force_capture());
+                previous_hook(info)
+            }
+        }))
+    })
+}
+
+/// Runs `f`, returns `fallback` if it panics.
+pub(crate) fn catch<R>(fallback: R, f: impl FnOnce() -> R) -> R {
+    let was_in_host_call = IN_HOST_CALL.replace(true);
+    let result = std::panic::catch_unwind(AssertUnwindSafe(f));
+    IN_HOST_CALL.set(was_in_host_call);
+
+    result.unwrap_or_else(|_| {
+        let report = PANIC_REPORT.take().unwrap_or_else(|| "unknown panic".to_string());
+        *LAST_PANIC.lock().unwrap_or_else(|poisoned| poisoned.into_inner()) = Some(report);
+        fallback
+    })
+}
+
+/// Writes the report of the last caught panic to `sink`, if there was one since the last call.
+pub(crate) extern "C" fn take_panic(sink: &mut ByteSink) {
+    let report = LAST_PANIC.lock().unwrap_or_else(|poisoned| poisoned.into_inner()).take();
+    if let Some(report) = report {
+        sink.write(report.as_bytes())
+    }
+}
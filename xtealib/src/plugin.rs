@@ -0,0 +1,318 @@
+//! The C ABI between xtea and its plugins.
+//!
+//! A plugin exports a single `extern "C"` entry point, [`ENTRY_POINT_SYMBOL`], which returns a
+//! [`PluginVTable`]. Everything that crosses the boundary is `#[repr(C)]`, so the host and
+//! the plugin don't need to be built by the same compiler, nor share an allocator.
+
+use std::{ffi::c_void, sync::{atomic::{AtomicU64, Ordering}, PoisonError, RwLock}};
+
+use imgui::sys::{ImGuiContext, ImGuiMemAllocFunc, ImGuiMemFreeFunc};
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use once_cell::sync::OnceCell;
+
+use crate::{ffi::{FfiSlice, FfiStr, UiHandle}, logs::LogFilter};
+
+/// Name of the function that returns the plugin's [`PluginVTable`].
+pub const ENTRY_POINT_SYMBOL: &str = "xtea_plugin_entry";
+
+pub type EntryPoint = extern "C" fn() -> *const PluginVTable;
+
+/// The plugin's state, only known by the plugin. The host just passes it back.
+#[repr(C)]
+pub struct PluginInstance {
+    _private: [u8; 0],
+}
+
+/// The functions a plugin provides to the host.
+///
+/// New functions are only ever appended, and they are always optional, so the host can tell
+/// from `size` which functions a plugin built against an older xtealib has,
+/// and skip the optional ones it doesn't provide.
+#[repr(C)]
+#[derive(Clone, Copy)]
+pub struct PluginVTable {
+    /// `size_of::<PluginVTable>()` of the xtealib the plugin was built with.
+    pub size: usize,
+    pub get_name: extern "C" fn() -> FfiStr<'static>,
+    pub connect_host: extern "C" fn(host: &HostApi),
+    /// Creates the plugin's state. Called in a separate thread.
+    pub create: extern "C" fn() -> *mut PluginInstance,
+    pub destroy: extern "C" fn(instance: *mut PluginInstance),
+    // Everything after this point is optional
+    pub build_ui: Option<extern "C" fn(instance: *mut PluginInstance, ui: UiHandle)>,
+    pub view_submenu: Option<extern "C" fn(instance: *mut PluginInstance, ui: UiHandle)>,
+    /// Asks the plugin to stop its tasks within `timeout_ms` milliseconds. Called before `destroy`.
+    pub shutdown: Option<extern "C" fn(instance: *mut PluginInstance, timeout_ms: u64)>,
+    /// How many of the plugin's [tasks](crate::tasks) are still running.
+    /// The library isn't unloaded until it's 0.
+    pub running_tasks: Option<extern "C" fn() -> usize>,
+    /// Writes the instance's state to `sink` before the plugin is reloaded. Writes nothing if it has no state.
+    pub save_state: Option<extern "C" fn(instance: *const PluginInstance, sink: &mut ByteSink)>,
+    /// Gives the state saved by the previous instance to the new one, right after `create`.
+    pub restore_state: Option<extern "C" fn(instance: *mut PluginInstance, state: FfiSlice<u8>)>,
+    /// Writes the report of the last panic caught in the plugin to `sink`, nothing if there was none.
+    /// After a panic the instance must not be used again, besides `destroy`.
+    pub take_panic: Option<extern "C" fn(sink: &mut ByteSink)>,
+    /// Like `create`, but if the plugin can't be created it returns null and writes why to `error`.
+    /// Used instead of `create` when the plugin provides it.
+    pub try_create: Option<extern "C" fn(error: &mut ByteSink) -> *mut PluginInstance>,
+    /// Asks the plugin to open a file given on xtea's command line. Returns whether it did.
+    pub open_file: Option<extern "C" fn(instance: *mut PluginInstance, path: FfiStr) -> bool>,
+    /// Replaces the filter of the plugin's log records, written like a [`LogFilter`].
+    /// Called right after `connect_host`, and whenever the filter changes.
+    pub set_log_filter: Option<extern "C" fn(filter: FfiStr)>,
+    /// Waits up to `timeout_ms` milliseconds for the plugin's tasks and joins their threads, so none of them
+    /// still runs the library's code. Returns how many are still running.
+    /// Used instead of `running_tasks` when the plugin provides it. Called after `shutdown`.
+    pub join_tasks: Option<extern "C" fn(timeout_ms: u64) -> usize>,
+}
+
+impl PluginVTable {
+    /// Size of the functions every plugin must provide.
+    pub const REQUIRED_SIZE: usize = std::mem::offset_of!(PluginVTable, build_ui);
+
+    /// Reads a vtable of any size. The functions the plugin doesn't have are `None`.
+    ///
+    /// # Safety
+    /// `vtable` must point to a vtable of at least [`PluginVTable::REQUIRED_SIZE`] bytes,
+    /// which must be its `size` field.
+    pub unsafe fn read(vtable: *const PluginVTable) -> PluginVTable {
+        let size = vtable.cast::<usize>().read().min(std::mem::size_of::<PluginVTable>());
+        let mut full_vtable = std::mem::MaybeUninit::<PluginVTable>::zeroed();
+        std::ptr::copy_nonoverlapping(vtable.cast::<u8>(), full_vtable.as_mut_ptr().cast::<u8>(), size);
+        full_vtable.assume_init()
+    }
+}
+
+/// Where one side writes bytes for the other. The bytes are copied, so they stay owned by the writer.
+#[repr(C)]
+pub struct ByteSink {
+    pub buffer: *mut c_void,
+    pub write: extern "C" fn(buffer: *mut c_void, bytes: FfiSlice<u8>),
+}
+
+impl ByteSink {
+    /// A sink that appends to `buffer`.
+    pub fn for_vec(buffer: &mut Vec<u8>) -> ByteSink {
+        ByteSink { buffer: (buffer as *mut Vec<u8>).cast(), write: extend_vec }
+    }
+
+    pub fn write(&mut self, bytes: &[u8]) {
+        (self.write)(self.buffer, FfiSlice::new(bytes))
+    }
+}
+
+extern "C" fn extend_vec(buffer: *mut c_void, bytes: FfiSlice<u8>) {
+    let buffer = unsafe { &mut *buffer.cast::<Vec<u8>>() };
+    buffer.extend_from_slice(unsafe { bytes.as_slice() })
+}
+
+/// What the host gives to a plugin before initializing it.
+#[repr(C)]
+pub struct HostApi {
+    /// `size_of::<HostApi>()` of the xtealib the host was built with.
+    pub size: usize,
+    pub imgui_context: *mut ImGuiContext,
+    pub alloc_func: ImGuiMemAllocFunc,
+    pub free_func: ImGuiMemFreeFunc,
+    pub alloc_user_data: *mut c_void,
+    /// Most verbose level the host wants, as a [`LevelFilter`] (0 is `Off`, 5 is `Trace`).
+    pub max_log_level: usize,
+    pub log: extern "C" fn(record: &FfiRecord),
+    // Everything after this point is optional
+    /// Given back in every [`FfiLogRecord`], so the host knows which plugin logged it.
+    pub log_source: u64,
+    /// Receives structured log records. Used instead of `log` when the host provides it.
+    pub log_record: Option<extern "C" fn(record: &FfiLogRecord)>,
+}
+
+impl HostApi {
+    /// Size of the fields every host provides.
+    pub const REQUIRED_SIZE: usize = std::mem::offset_of!(HostApi, log_source);
+
+    /// Shares the host's current imgui context and allocator, and sends the plugin's log records
+    /// to `log_record`, tagged with `log_source`.
+    pub fn new(
+        max_log_level: LevelFilter,
+        log: extern "C" fn(record: &FfiRecord),
+        log_source: u64,
+        log_record: extern "C" fn(record: &FfiLogRecord),
+    ) -> HostApi {
+        let malloc = &mut None;
+        let free = &mut None;
+        let user_data = &mut std::ptr::null_mut();
+        unsafe { imgui::sys::igGetAllocatorFunctions(malloc, free, user_data) };
+        HostApi {
+            size: std::mem::size_of::<HostApi>(),
+            imgui_context: unsafe { imgui::sys::igGetCurrentContext() },
+            alloc_func: *malloc,
+            free_func: *free,
+            alloc_user_data: *user_data,
+            max_log_level: max_log_level as usize,
+            log,
+            log_source,
+            log_record: Some(log_record),
+        }
+    }
+
+    /// Reads a `HostApi` of any size. The fields the host doesn't have are zeroed.
+    ///
+    /// # Safety
+    /// `host` must point to a `HostApi` of at least [`HostApi::REQUIRED_SIZE`] bytes, which must be its `size` field.
+    pub unsafe fn read(host: *const HostApi) -> HostApi {
+        let size = (*host).size.min(std::mem::size_of::<HostApi>());
+        let mut full_host = std::mem::MaybeUninit::<HostApi>::zeroed();
+        std::ptr::copy_nonoverlapping(host.cast::<u8>(), full_host.as_mut_ptr().cast::<u8>(), size);
+        full_host.assume_init()
+    }
+}
+
+/// A log message sent from a plugin to the host.
+#[repr(C)]
+pub struct FfiRecord<'a> {
+    /// As a [`Level`] (1 is `Error`, 5 is `Trace`).
+    pub level: usize,
+    pub target: FfiStr<'a>,
+    pub message: FfiStr<'a>,
+}
+
+impl FfiRecord<'_> {
+    pub fn level(&self) -> Level {
+        Level::iter().nth(self.level.saturating_sub(1)).unwrap_or(Level::Trace)
+    }
+}
+
+/// A log record sent from a plugin to the host, with everything the `log` macros captured.
+#[repr(C)]
+pub struct FfiLogRecord<'a> {
+    /// `size_of::<FfiLogRecord>()` of the xtealib the plugin was built with, fields are only ever appended.
+    pub size: usize,
+    /// The host's [`HostApi::log_source`].
+    pub source: u64,
+    /// As a [`Level`] (1 is `Error`, 5 is `Trace`).
+    pub level: usize,
+    pub target: FfiStr<'a>,
+    pub message: FfiStr<'a>,
+    /// Empty when unknown.
+    pub module_path: FfiStr<'a>,
+    /// Empty when unknown.
+    pub file: FfiStr<'a>,
+    /// 0 when unknown.
+    pub line: u32,
+    pub fields: FfiSlice<'a, FfiField<'a>>,
+}
+
+impl FfiLogRecord<'_> {
+    pub fn level(&self) -> Level {
+        Level::iter().nth(self.level.saturating_sub(1)).unwrap_or(Level::Trace)
+    }
+}
+
+/// A key-value of a log record, its value formatted with `Display`.
+#[repr(C)]
+pub struct FfiField<'a> {
+    pub key: FfiStr<'a>,
+    pub value: FfiStr<'a>,
+}
+
+pub fn level_filter(max_log_level: usize) -> LevelFilter {
+    LevelFilter::iter().nth(max_log_level).unwrap_or(LevelFilter::Trace)
+}
+
+/// Points the plugin's copies of imgui and log to the host's.
+pub(crate) extern "C" fn connect_host(host: &HostApi) {
+    crate::panic::set_panic_hook();
+    unsafe {
+        imgui::sys::igSetCurrentContext(host.imgui_context);
+        imgui::sys::igSetAllocatorFunctions(host.alloc_func, host.free_func, host.alloc_user_data);
+    }
+
+    let host = unsafe { HostApi::read(host) };
+    // Both fail if the library was already connected, which only happens if it was never unloaded
+    let _ = HOST_LOGGER.log.set(host.log);
+    if let Some(log_record) = host.log_record {
+        let _ = HOST_LOGGER.log_record.set(log_record);
+    }
+    HOST_LOGGER.source.store(host.log_source, Ordering::Relaxed);
+    let _ = log::set_logger(&HOST_LOGGER);
+    log::set_max_level(level_filter(host.max_log_level));
+}
+
+/// Filters the plugin's log records before they're sent to the host.
+pub(crate) extern "C" fn set_log_filter(filter: FfiStr) {
+    // The host only sends filters it could parse itself
+    if let Ok(filter) = unsafe { filter.as_str() }.parse::<LogFilter>() {
+        log::set_max_level(filter.max_level());
+        *HOST_LOGGER.filter.write().unwrap_or_else(PoisonError::into_inner) = filter
+    }
+}
+
+static HOST_LOGGER: HostLogger = HostLogger {
+    log: OnceCell::new(),
+    log_record: OnceCell::new(),
+    source: AtomicU64::new(0),
+    filter: RwLock::new(LogFilter::new(LevelFilter::Trace)),
+};
+
+/// Forwards the plugin's log records to the host.
+struct HostLogger {
+    log: OnceCell<extern "C" fn(record: &FfiRecord)>,
+    log_record: OnceCell<extern "C" fn(record: &FfiLogRecord)>,
+    source: AtomicU64,
+    /// Set by the host with `set_log_filter`, hosts without it only set the max level.
+    filter: RwLock<LogFilter>,
+}
+
+impl Log for HostLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= log::max_level()
+            && self.filter.read().unwrap_or_else(PoisonError::into_inner).enabled(metadata.target(), metadata.level())
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.enabled(record.metadata()) {
+            return
+        }
+        let message = record.args().to_string();
+        if let Some(log_record) = self.log_record.get() {
+            let fields = crate::logs::fields(record);
+            let fields = fields.iter()
+                .map(|(key, value)| FfiField { key: FfiStr::new(key), value: FfiStr::new(value) })
+                .collect::<Vec<_>>();
+            log_record(&FfiLogRecord {
+                size: std::mem::size_of::<FfiLogRecord>(),
+                source: self.source.load(Ordering::Relaxed),
+                level: record.level() as usize,
+                target: FfiStr::new(record.target()),
+                message: FfiStr::new(&message),
+                module_path: FfiStr::new(record.module_path().unwrap_or_default()),
+                file: FfiStr::new(record.file().unwrap_or_default()),
+                line: record.line().unwrap_or_default(),
+                fields: FfiSlice::new(&fields),
+            })
+        } else if let Some(log) = self.log.get() {
+            log(&FfiRecord {
+                level: record.level() as usize,
+                target: FfiStr::new(record.target()),
+                message: FfiStr::new(&message),
+            })
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+/// Exports the plugin's [`AbiDescriptor`](crate::abi::AbiDescriptor) and [`PluginVTable`].
+/// Plugins should use [`xtea_plugin`](crate::xtea_plugin) instead.
+#[macro_export]
+macro_rules! declare_plugin {
+    ($vtable:expr) => {
+        $crate::declare_plugin_abi!();
+
+        #[no_mangle]
+        pub extern "C" fn xtea_plugin_entry() -> *const $crate::plugin::PluginVTable {
+            static VTABLE: $crate::plugin::PluginVTable = $vtable;
+            &VTABLE
+        }
+    };
+}
@@ -0,0 +1,71 @@
+//! Keeping a plugin's state across reloads with serde.
+//!
+//! ```ignore
+//! #[derive(Serialize, Deserialize)]
+//! struct MyPlugin {
+//!     show_window: bool,
+//!     // Not kept, recreated with `Default` after a reload
+//!     #[serde(skip)]
+//!     texture: Option<TextureId>,
+//! }
+//!
+//! xtealib::xtea_plugin!(MyPlugin, serde_state);
+//! ```
+//!
+//! The state is saved as json, so fields added with `#[serde(default)]` don't lose the rest
+//! of the state. If it can't be restored, the plugin keeps what [`XteaPlugin::new`] created.
+
+use log::{error, warn};
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::{
+    ffi::FfiSlice,
+    panic,
+    plugin::{PluginInstance, PluginVTable, ByteSink},
+    XteaPlugin,
+};
+
+/// Serializes a plugin's state, for [`XteaPlugin::save_state`].
+pub fn save<T: Serialize>(state: &T) -> Option<Vec<u8>> {
+    match serde_json::to_vec(state) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            error!("Could not save the plugin state, it will be lost on reload\n\t{e}");
+            None
+        },
+    }
+}
+
+/// Deserializes a plugin's state, for [`XteaPlugin::restore_state`].
+pub fn restore<T: DeserializeOwned>(state: &[u8]) -> Option<T> {
+    match serde_json::from_slice(state) {
+        Ok(state) => Some(state),
+        Err(e) => {
+            warn!("Could not restore the plugin state, starting from scratch\n\t{e}");
+            None
+        },
+    }
+}
+
+impl PluginVTable {
+    /// Like [`PluginVTable::for_plugin`], but the whole plugin is kept across reloads.
+    pub const fn for_serde_plugin<T: XteaPlugin + Serialize + DeserializeOwned>() -> PluginVTable {
+        PluginVTable {
+            save_state: Some(save_state::<T>),
+            restore_state: Some(restore_state::<T>),
+            ..PluginVTable::for_plugin::<T>()
+        }
+    }
+}
+
+extern "C" fn save_state<T: Serialize>(plugin: *const PluginInstance, sink: &mut ByteSink) {
+    if let Some(state) = panic::catch(None, || save(unsafe { &*plugin.cast::<T>() })) {
+        sink.write(&state)
+    }
+}
+
+extern "C" fn restore_state<T: DeserializeOwned>(plugin: *mut PluginInstance, state: FfiSlice<u8>) {
+    panic::catch((), || if let Some(restored) = restore(unsafe { state.as_slice() }) {
+        unsafe { *plugin.cast::<T>() = restored }
+    })
+}
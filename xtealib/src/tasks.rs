@@ -0,0 +1,118 @@
+//! Background work a plugin does outside of the host's calls.
+//!
+//! The host won't unload a plugin's library while any of its tasks is running,
+//! so a plugin must start its threads with [`spawn`] (or hold a [`TaskGuard`] for any other work
+//! that can call into the library later), and stop them once [`is_cancelled`] returns `true`.
+
+use std::{
+    panic::AssertUnwindSafe,
+    sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, mpsc::{self, Receiver}, Mutex, MutexGuard, PoisonError},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+static RUNNING_TASKS: AtomicUsize = AtomicUsize::new(0);
+static CANCELLED: AtomicBool = AtomicBool::new(false);
+/// The threads started with [`spawn`]. They are joined before the library is unloaded,
+/// so none of them is still running the library's code (like its thread-locals' destructors).
+static THREADS: Mutex<Vec<JoinHandle<()>>> = Mutex::new(Vec::new());
+
+/// Keeps the plugin's library loaded while it's alive.
+pub struct TaskGuard {
+    _private: (),
+}
+
+impl TaskGuard {
+    pub fn new() -> TaskGuard {
+        RUNNING_TASKS.fetch_add(1, Ordering::SeqCst);
+        TaskGuard { _private: () }
+    }
+}
+
+impl Default for TaskGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for TaskGuard {
+    fn drop(&mut self) {
+        RUNNING_TASKS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// A task started with [`spawn`]. Dropping it doesn't detach the task, the host still waits for it.
+pub struct TaskHandle<T> {
+    result: Receiver<thread::Result<T>>,
+}
+
+impl<T> TaskHandle<T> {
+    /// Waits for the task to finish. Returns the panic's payload if it panicked.
+    pub fn join(self) -> thread::Result<T> {
+        // The task always sends its result before its thread ends
+        self.result.recv().unwrap_or_else(|_| Err(Box::new("the task's thread ended without a result")))
+    }
+}
+
+/// Like [`std::thread::spawn`], but the host waits for the thread before unloading the plugin.
+pub fn spawn<F, T>(f: F) -> TaskHandle<T>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let (sender, result) = mpsc::sync_channel(1);
+    // Locked before the thread starts, so the host can't miss it
+    let mut threads = lock_threads();
+    join_finished(&mut threads);
+    threads.push(thread::spawn(move || {
+        let _ = sender.send(std::panic::catch_unwind(AssertUnwindSafe(f)));
+    }));
+
+    TaskHandle { result }
+}
+
+/// Whether the plugin is shutting down. Running tasks should finish as soon as possible.
+pub fn is_cancelled() -> bool {
+    CANCELLED.load(Ordering::SeqCst)
+}
+
+pub(crate) fn set_cancelled(cancelled: bool) {
+    CANCELLED.store(cancelled, Ordering::SeqCst)
+}
+
+pub(crate) extern "C" fn running_tasks() -> usize {
+    RUNNING_TASKS.load(Ordering::SeqCst) + lock_threads().len()
+}
+
+/// Waits up to `timeout_ms` for the tasks, joining the threads of those that finished.
+/// Returns how many are still running.
+pub(crate) extern "C" fn join_tasks(timeout_ms: u64) -> usize {
+    let deadline = Instant::now() + Duration::from_millis(timeout_ms);
+    loop {
+        let running = {
+            let mut threads = lock_threads();
+            join_finished(&mut threads);
+            RUNNING_TASKS.load(Ordering::SeqCst) + threads.len()
+        };
+        if running == 0 || Instant::now() >= deadline {
+            return running
+        }
+        thread::sleep(Duration::from_millis(10))
+    }
+}
+
+fn lock_threads() -> MutexGuard<'static, Vec<JoinHandle<()>>> {
+    THREADS.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Joins the threads whose task is over. Their threads may still be ending, but not for long.
+fn join_finished(threads: &mut Vec<JoinHandle<()>>) {
+    for thread in std::mem::take(threads) {
+        if thread.is_finished() {
+            // The task's panics are caught, and given to its `TaskHandle`
+            let _ = thread.join();
+        } else {
+            threads.push(thread)
+        }
+    }
+}